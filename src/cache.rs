@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use ignore;
+use index;
+use index::{IndexError, Reader, Stamp};
+use stats;
+use log::*;
+
+const MAGIC: &[u8; 4] = b"FEWC";
+const VERSION: u64 = 1;
+
+// Flags stored with each entry.
+const FLAG_DIR: u8 = 1;
+const FLAG_IGNORED: u8 = 2;
+
+// When a directory was last modified and its inode, or `None` if it couldn't be stat'ed.
+// Directories are modified when entries are added to, removed from or renamed in them, and a
+// directory that was replaced by another has a different inode.
+pub type DirStamp = Option<(u64, u32, u64)>;
+
+pub fn dir_stamp (path: &path::Path) -> DirStamp {
+    stats::add(&stats::STAT_CALLS, 1);
+    let metadata = fs::symlink_metadata(path).ok()?;
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos(), metadata.ino()))
+}
+
+type Sources = Vec<(path::PathBuf, Stamp)>;
+
+pub struct CachedEntry {
+    pub name: OsString,
+    pub is_dir: bool,
+    // What the ignore rules decided when the entry was last searched.
+    pub ignored: bool,
+}
+
+pub struct CachedDir {
+    pub stamp: DirStamp,
+    // When the directory's .gitignore was modified, or `None` if it doesn't have one.
+    pub ignore_file: Stamp,
+    pub entries: Vec<CachedEntry>,
+}
+
+// The directory listings of the last search of a root, so the next search of it only has to read
+// directories that changed since. Entries also remember if they were ignored, which holds until an
+// ignore file that applies to them changes.
+pub struct WalkCache {
+    // The canonical path of the search root.
+    root: path::PathBuf,
+    search_dir: path::PathBuf,
+    // The ignore files outside of the root and when they were modified.
+    sources: Sources,
+    // Whether any of `sources` changed since the cache was written, which means every cached
+    // ignore decision has to be made again.
+    pub sources_changed: bool,
+    // Keyed by directory path relative to the search root.
+    dirs: HashMap<path::PathBuf, CachedDir>,
+    // Directories listed by this search, which replace `dirs` when the cache is saved.
+    walked: Mutex<Vec<(path::PathBuf, CachedDir)>>,
+}
+
+impl WalkCache {
+
+    pub fn load (options: &super::Options) -> Option<WalkCache> {
+        //! Reads the walk cache of `options.search_dir`. A cache that can't be read starts out empty.
+        let mut cache = WalkCache::new(options)?;
+        let cache_path = index::cache_file(&cache.root, "walk");
+        let bytes = match fs::read(&cache_path) {
+            Ok(b) => b,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    v(format!("Failed to read the walk cache {:?}: {}", cache_path, e), options);
+                }
+                return Some(cache);
+            },
+        };
+        match cache.restore(&bytes) {
+            Ok(()) => v(format!("Found a walk cache of {} directories at {:?}", cache.dirs.len(), cache_path), options),
+            Err(e) => v(format!("The walk cache {:?} can't be used: {}", cache_path, e), options),
+        }
+        Some(cache)
+    }
+
+    fn new (options: &super::Options) -> Option<WalkCache> {
+        //! An empty cache of `options.search_dir` with the ignore files that apply to it now.
+        let search_dir = path::PathBuf::from(&options.search_dir);
+        let root = search_dir.canonicalize().ok()?;
+        let sources: Sources = ignore::RuleSet::root_ignore_files(&search_dir, options)
+            .into_iter()
+            .map(|(ignore_file, _)| {
+                let modified = index::stamp(&ignore_file);
+                (ignore_file, modified)
+            })
+            .collect();
+        Some(WalkCache {
+            root,
+            search_dir,
+            sources,
+            sources_changed: true,
+            dirs: HashMap::new(),
+            walked: Mutex::new(vec![]),
+        })
+    }
+
+    fn restore (&mut self, bytes: &[u8]) -> Result<(), String> {
+        //! Fills the cache with the directories in `bytes`, as written by `save`, and notes whether
+        //! the ignore files outside of the root changed since.
+        let (root, sources, dirs) = match WalkCache::decode(bytes) {
+            Ok(decoded) => decoded,
+            Err(IndexError::Invalid(e)) => return Err(format!("it isn't valid: {}", e)),
+            Err(IndexError::Io(e)) => return Err(e.to_string()),
+        };
+        if root != self.root {
+            return Err(String::from("it's for another directory"));
+        }
+        self.sources_changed = sources != self.sources;
+        self.dirs = dirs;
+        Ok(())
+    }
+
+    pub fn get (&self, dir: &path::Path) -> Option<&CachedDir> {
+        self.dirs.get(dir.strip_prefix(&self.search_dir).ok()?)
+    }
+
+    pub fn add (&self, dir: &path::Path, listed: CachedDir) {
+        if let Ok(relative) = dir.strip_prefix(&self.search_dir) {
+            self.walked.lock().unwrap().push((relative.to_path_buf(), listed));
+        }
+    }
+
+    pub fn save (self, options: &super::Options) {
+        //! Replaces the cache with the directories listed by this search. Directories that weren't
+        //! listed, because they were removed or are now ignored, are forgotten.
+        let cache_path = index::cache_file(&self.root, "walk");
+        let dirs = self.walked.into_inner().unwrap();
+        if let Err(e) = index::write_cache_file(&cache_path, &WalkCache::encode(&self.root, &self.sources, &dirs)) {
+            v(format!("Failed to write the walk cache {:?}: {}", cache_path, e), options);
+        }
+    }
+
+    fn encode (root: &path::Path, sources: &[(path::PathBuf, Stamp)], dirs: &[(path::PathBuf, CachedDir)]) -> Vec<u8> {
+        //! Written like an index, see `Index::encode`: `FEWC` and the format version, the canonical
+        //! search root, the sources with their modification stamps, then each directory's path,
+        //! stamp, .gitignore modification stamp and entries, each entry a name and flags.
+        let mut bytes = index::start_file(MAGIC, VERSION);
+        index::write_bytes(&mut bytes, root.as_os_str().as_bytes());
+        index::write_varint(&mut bytes, sources.len() as u64);
+        for &(ref source, modified) in sources {
+            index::write_bytes(&mut bytes, source.as_os_str().as_bytes());
+            index::write_stamp(&mut bytes, modified);
+        }
+        index::write_varint(&mut bytes, dirs.len() as u64);
+        for (dir, listed) in dirs {
+            index::write_bytes(&mut bytes, dir.as_os_str().as_bytes());
+            match listed.stamp {
+                Some((seconds, nanoseconds, inode)) => {
+                    bytes.push(1);
+                    index::write_varint(&mut bytes, seconds);
+                    index::write_varint(&mut bytes, u64::from(nanoseconds));
+                    index::write_varint(&mut bytes, inode);
+                },
+                None => bytes.push(0),
+            }
+            index::write_stamp(&mut bytes, listed.ignore_file);
+            index::write_varint(&mut bytes, listed.entries.len() as u64);
+            for entry in &listed.entries {
+                index::write_bytes(&mut bytes, entry.name.as_bytes());
+                let flags = if entry.is_dir { FLAG_DIR } else { 0 } | if entry.ignored { FLAG_IGNORED } else { 0 };
+                bytes.push(flags);
+            }
+        }
+        index::end_file(bytes)
+    }
+
+    fn decode (bytes: &[u8]) -> Result<(path::PathBuf, Sources, HashMap<path::PathBuf, CachedDir>), IndexError> {
+        let mut reader = Reader::open(bytes, MAGIC, VERSION)?;
+        let root = path::PathBuf::from(OsStr::from_bytes(reader.bytes()?));
+        let source_count = reader.count()?;
+        let mut sources = Vec::with_capacity(source_count);
+        for _ in 0..source_count {
+            let source = path::PathBuf::from(OsStr::from_bytes(reader.bytes()?));
+            sources.push((source, reader.stamp()?));
+        }
+        let dir_count = reader.count()?;
+        let mut dirs = HashMap::with_capacity(dir_count);
+        for _ in 0..dir_count {
+            let dir = path::PathBuf::from(OsStr::from_bytes(reader.bytes()?));
+            let stamp = match reader.byte()? {
+                0 => None,
+                1 => Some((reader.varint()?, reader.varint()? as u32, reader.varint()?)),
+                b => return Err(IndexError::Invalid(format!("unexpected byte {} in a directory stamp", b))),
+            };
+            let ignore_file = reader.stamp()?;
+            let entry_count = reader.count()?;
+            let mut entries = Vec::with_capacity(entry_count);
+            for _ in 0..entry_count {
+                let name = OsStr::from_bytes(reader.bytes()?).to_os_string();
+                let flags = reader.byte()?;
+                entries.push(CachedEntry {
+                    name,
+                    is_dir: flags & FLAG_DIR != 0,
+                    ignored: flags & FLAG_IGNORED != 0,
+                });
+            }
+            dirs.insert(dir, CachedDir {
+                stamp,
+                ignore_file,
+                entries,
+            });
+        }
+        reader.finish()?;
+        Ok((root, sources, dirs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    fn listing (stamp: DirStamp) -> CachedDir {
+        CachedDir {
+            stamp,
+            ignore_file: Some((1_700_000_000, 5)),
+            entries: vec![
+                CachedEntry { name: OsString::from("src"), is_dir: true, ignored: false },
+                CachedEntry { name: OsStr::from_bytes(b"caf\xe9").to_os_string(), is_dir: false, ignored: false },
+                CachedEntry { name: OsString::from("target"), is_dir: true, ignored: true },
+            ],
+        }
+    }
+
+    fn set_modified (path: &path::Path, seconds: u64) {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        fs::File::open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn round_trip () {
+        let root = path::Path::new("/home/me/project");
+        let sources = vec![(path::PathBuf::from("/home/me/.gitignore"), Some((1, 2))), (path::PathBuf::from("/etc/ignore"), None)];
+        let dirs = vec![(path::PathBuf::from(""), listing(Some((10, 20, 30)))), (path::PathBuf::from("src"), listing(None))];
+        let (decoded_root, decoded_sources, decoded_dirs) = match WalkCache::decode(&WalkCache::encode(root, &sources, &dirs)) {
+            Ok(decoded) => decoded,
+            Err(_) => panic!("the walk cache didn't decode"),
+        };
+        assert_eq!(decoded_root, root);
+        assert_eq!(decoded_sources, sources);
+        assert_eq!(decoded_dirs.len(), 2);
+        for (dir, listed) in &dirs {
+            let decoded = &decoded_dirs[dir];
+            assert_eq!(decoded.stamp, listed.stamp);
+            assert_eq!(decoded.ignore_file, listed.ignore_file);
+            let entries = |d: &CachedDir| d.entries.iter().map(|e| (e.name.clone(), e.is_dir, e.ignored)).collect::<Vec<_>>();
+            assert_eq!(entries(decoded), entries(listed));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_files () {
+        let bytes = WalkCache::encode(path::Path::new("/r"), &[], &[(path::PathBuf::from("a"), listing(Some((1, 2, 3))))]);
+        let invalid = |bytes: &[u8]| match WalkCache::decode(bytes) {
+            Err(IndexError::Invalid(e)) => e,
+            _ => panic!("expected an invalid walk cache"),
+        };
+        assert_eq!(invalid(&bytes[..bytes.len() - 1]), "its checksum doesn't match, it may be corrupt");
+        let mut flipped = bytes.clone();
+        flipped[10] ^= 1;
+        assert_eq!(invalid(&flipped), "its checksum doesn't match, it may be corrupt");
+        let mut index = bytes.clone();
+        index[..4].copy_from_slice(b"FEIX");
+        assert_eq!(invalid(&index), "it's not a file fe wrote");
+        assert_eq!(invalid(b""), "it's not a file fe wrote");
+    }
+
+    #[test]
+    fn changes_force_reading_again () {
+        let dir = super::super::test_dir("cache_changes").canonicalize().unwrap();
+        fs::create_dir_all(dir.join(".git/info")).unwrap();
+        fs::write(dir.join(".git/info/exclude"), "*.o\n").unwrap();
+        set_modified(&dir.join(".git/info/exclude"), 1_000_000);
+        fs::create_dir_all(dir.join("root/sub")).unwrap();
+        set_modified(&dir.join("root/sub"), 1_000_000);
+        let options = super::super::Options {
+            search_dir: dir.join("root").to_string_lossy().into_owned(),
+            ..super::super::Options::for_tests()
+        };
+        let sub = dir.join("root/sub");
+
+        let cache = WalkCache::new(&options).unwrap();
+        assert!(cache.sources.iter().any(|s| s.0 == dir.join(".git/info/exclude")));
+        cache.add(&sub, listing(dir_stamp(&sub)));
+        let bytes = WalkCache::encode(&cache.root, &cache.sources, &cache.walked.into_inner().unwrap());
+
+        // Nothing changed, so the listing is used and what it says is ignored still is.
+        let mut cache = WalkCache::new(&options).unwrap();
+        assert!(cache.restore(&bytes).is_ok());
+        assert!(!cache.sources_changed);
+        assert_eq!(cache.get(&sub).map(|c| c.stamp), Some(dir_stamp(&sub)));
+
+        // Adding a file changes the directory's stamp, so it's read again.
+        fs::write(sub.join("new"), "").unwrap();
+        set_modified(&sub, 2_000_000);
+        let mut cache = WalkCache::new(&options).unwrap();
+        assert!(cache.restore(&bytes).is_ok());
+        assert!(cache.get(&sub).is_some_and(|c| c.stamp != dir_stamp(&sub)));
+
+        // Editing an ignore file outside of the root means every ignore decision is made again.
+        set_modified(&dir.join(".git/info/exclude"), 2_000_000);
+        let mut cache = WalkCache::new(&options).unwrap();
+        assert!(cache.restore(&bytes).is_ok());
+        assert!(cache.sources_changed);
+
+        // A cache of another directory isn't used.
+        let other = super::super::Options {
+            search_dir: dir.to_string_lossy().into_owned(),
+            ..options.clone()
+        };
+        let mut cache = WalkCache::new(&other).unwrap();
+        assert_eq!(cache.restore(&bytes), Err(String::from("it's for another directory")));
+        assert!(cache.dirs.is_empty());
+    }
+}
@@ -0,0 +1,702 @@
+use libc;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::io;
+use std::io::{BufRead, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use find;
+use find::Match;
+use ignore;
+use index;
+use index::Stamp;
+use json;
+use json::Json;
+use log::*;
+
+// How long to wait for more changes after one is seen, so a burst of them, like a checkout, is
+// applied all at once.
+const SETTLE_TIME_MS: i32 = 20;
+
+// How long `fe` waits for a daemon to answer before searching by itself.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// The longest query line the daemon reads, queries are a few short fields.
+const MAX_QUERY_LENGTH: u64 = 64 * 1024;
+
+// Changes to a directory that change what's in it, or its .gitignore.
+const WATCH_MASK: u32 = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO
+    | libc::IN_CLOSE_WRITE | libc::IN_ONLYDIR | libc::IN_DONT_FOLLOW | libc::IN_EXCL_UNLINK;
+
+// The size of an inotify event without its name.
+const EVENT_HEADER_SIZE: usize = 16;
+
+struct Event {
+    wd: i32,
+    mask: u32,
+    name: OsString,
+}
+
+struct Inotify {
+    fd: libc::c_int,
+}
+
+impl Inotify {
+
+    fn new () -> io::Result<Inotify> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            fd,
+        })
+    }
+
+    fn watch (&self, dir: &path::Path) -> io::Result<i32> {
+        let dir = CString::new(dir.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, dir.as_ptr(), WATCH_MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    fn unwatch (&self, wd: i32) {
+        // Fails if the directory is already gone, which removes its watch anyway.
+        unsafe { libc::inotify_rm_watch(self.fd, wd) };
+    }
+
+    fn read (&self, timeout_ms: i32) -> io::Result<Vec<Event>> {
+        //! Waits up to `timeout_ms`, or forever if it's negative, for events and returns them.
+        let mut poll = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, timeout_ms) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                return Ok(vec![]);
+            }
+            return Err(e);
+        }
+        if ready == 0 {
+            return Ok(vec![]);
+        }
+        let mut buffer = vec![0u8; 64 * 1024];
+        let read = unsafe { libc::read(self.fd, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+        if read < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let buffer = &buffer[..read as usize];
+        let field = |at: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&buffer[at..at + 4]);
+            bytes
+        };
+        let mut events = vec![];
+        let mut at = 0;
+        while at + EVENT_HEADER_SIZE <= buffer.len() {
+            let name_len = u32::from_ne_bytes(field(at + 12)) as usize;
+            let name = &buffer[at + EVENT_HEADER_SIZE..(at + EVENT_HEADER_SIZE + name_len).min(buffer.len())];
+            // Names are padded with NULs.
+            let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+            events.push(Event {
+                wd: i32::from_ne_bytes(field(at)),
+                mask: u32::from_ne_bytes(field(at + 4)),
+                name: OsStr::from_bytes(name).to_os_string(),
+            });
+            at += EVENT_HEADER_SIZE + name_len;
+        }
+        Ok(events)
+    }
+}
+
+impl Drop for Inotify {
+
+    fn drop (&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+struct WatchedDir {
+    // `None` if the directory couldn't be watched, then changes to it go unnoticed.
+    wd: Option<i32>,
+    // The rules of the directory's parents, to check its entries again with when it changes.
+    parent_rules: Arc<ignore::RuleSet>,
+    // The entries' paths, whether they're directories and whether they're ignored.
+    entries: Vec<find::ScannedEntry>,
+}
+
+impl WatchedDir {
+
+    fn subdirectories (&self) -> HashSet<path::PathBuf> {
+        self.entries.iter().filter(|e| e.1 && !e.2).map(|e| e.0.clone()).collect()
+    }
+}
+
+// Every directory under the root that isn't ignored, with what's in it, kept up to date by watching
+// them with inotify. Directories are listed by the daemon itself rather than by `find::walk` so
+// it knows the rules for each of them, and when one changes only it has to be listed again.
+struct Table<'a> {
+    search_dir: path::PathBuf,
+    dirs: HashMap<path::PathBuf, WatchedDir>,
+    watches: HashMap<i32, path::PathBuf>,
+    // The ignore files outside of the root and when they were modified. They aren't watched, they
+    // are checked before answering each query instead.
+    sources: Vec<(path::PathBuf, Stamp)>,
+    inotify: &'a Inotify,
+    options: &'a super::Options,
+}
+
+impl<'a> Table<'a> {
+
+    fn scan (&mut self, dir: path::PathBuf, parent_rules: Arc<ignore::RuleSet>) {
+        //! Lists and watches `dir` and every directory in it that isn't ignored.
+        let mut stack = vec![(dir, parent_rules)];
+        while let Some((dir, parent_rules)) = stack.pop() {
+            // Watched before listing it so nothing created in between is missed.
+            let wd = match self.inotify.watch(&dir) {
+                Ok(wd) => Some(wd),
+                Err(e) => {
+                    v(format!("Failed to watch {:?}, changes to it won't be noticed: {}", dir, e), self.options);
+                    None
+                },
+            };
+            let (rules, entries) = match find::scan_dir(&dir, &parent_rules, self.options) {
+                Some(scanned) => scanned,
+                None => continue,
+            };
+            let watched = WatchedDir {
+                wd,
+                parent_rules,
+                entries,
+            };
+            for subdirectory in watched.subdirectories() {
+                if !self.dirs.contains_key(&subdirectory) {
+                    stack.push((subdirectory, rules.clone()));
+                }
+            }
+            if let Some(wd) = wd {
+                self.watches.insert(wd, dir.clone());
+            }
+            self.dirs.insert(dir, watched);
+        }
+    }
+
+    fn forget (&mut self, dir: &path::Path) {
+        //! Stops watching `dir` and every directory in it.
+        let gone: Vec<path::PathBuf> = self.dirs.keys().filter(|d| d.starts_with(dir)).cloned().collect();
+        for d in gone {
+            if let Some(wd) = self.dirs.remove(&d).and_then(|w| w.wd) {
+                self.watches.remove(&wd);
+                self.inotify.unwatch(wd);
+            }
+        }
+    }
+
+    fn rescan (&mut self, dir: &path::Path, rules_changed: bool) {
+        //! Lists `dir` again after it changed. If its .gitignore changed everything in it is listed
+        //! again, otherwise only directories that are new to it.
+        let parent_rules = match self.dirs.get(dir) {
+            Some(d) => d.parent_rules.clone(),
+            None => return,
+        };
+        if rules_changed {
+            v(format!("The .gitignore in {:?} changed.", dir), self.options);
+            self.forget(dir);
+            self.scan(dir.to_path_buf(), parent_rules);
+            return;
+        }
+        let (rules, entries) = match find::scan_dir(dir, &parent_rules, self.options) {
+            Some(scanned) => scanned,
+            None => {
+                self.forget(dir);
+                return;
+            },
+        };
+        vv(format!("{:?} changed.", dir), self.options);
+        let (before, after) = match self.dirs.get_mut(dir) {
+            Some(watched) => {
+                let before = watched.subdirectories();
+                watched.entries = entries;
+                (before, watched.subdirectories())
+            },
+            None => return,
+        };
+        for removed in before.difference(&after) {
+            self.forget(removed);
+        }
+        for added in after.difference(&before) {
+            self.scan(added.clone(), rules.clone());
+        }
+    }
+
+    fn rescan_all (&mut self) {
+        let search_dir = self.search_dir.clone();
+        self.forget(&search_dir);
+        self.sources = current_sources(&search_dir, self.options);
+        let root_rules = Arc::new(ignore::RuleSet::new_root(&search_dir, self.options));
+        self.scan(search_dir, root_rules);
+    }
+
+    fn apply (&mut self, events: Vec<Event>) {
+        let mut changed: HashMap<path::PathBuf, bool> = HashMap::new();
+        for event in events {
+            if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                v(String::from("Too many changes to keep up with, listing everything again."), self.options);
+                self.rescan_all();
+                return;
+            }
+            let dir = match self.watches.get(&event.wd) {
+                Some(d) => d.clone(),
+                None => continue,
+            };
+            let rules_changed = ignore::is_ignore_file(&event.name, self.options);
+            // Writing to files only matters for ignore files.
+            if event.mask & !libc::IN_CLOSE_WRITE == 0 && !rules_changed {
+                continue;
+            }
+            *changed.entry(dir).or_insert(false) |= rules_changed;
+        }
+        // Parents first, listing them again may forget their subdirectories.
+        let mut changed: Vec<(path::PathBuf, bool)> = changed.into_iter().collect();
+        changed.sort_by_key(|c| c.0.components().count());
+        for (dir, rules_changed) in changed {
+            self.rescan(&dir, rules_changed);
+        }
+    }
+
+    fn paths<'s> (&'s self) -> impl Iterator<Item = &'s path::Path> + 's {
+        self.dirs.values()
+            .flat_map(|d| d.entries.iter())
+            .filter(|e| !e.2)
+            .map(|e| e.0.as_path())
+    }
+}
+
+fn current_sources (search_dir: &path::Path, options: &super::Options) -> Vec<(path::PathBuf, Stamp)> {
+    ignore::RuleSet::root_ignore_files(search_dir, options)
+        .into_iter()
+        .map(|(ignore_file, _)| {
+            let modified = index::stamp(&ignore_file);
+            (ignore_file, modified)
+        })
+        .collect()
+}
+
+fn socket_path (root: &path::Path) -> path::PathBuf {
+    //! Sockets are kept in `$XDG_RUNTIME_DIR/fe`, or with indexes if there isn't one, and named
+    //! after the root they're for.
+    let fallback = index::cache_file(root, "sock");
+    match (env::var_os("XDG_RUNTIME_DIR"), fallback.file_name()) {
+        (Some(d), Some(name)) if !d.is_empty() => path::PathBuf::from(d).join("fe").join(name),
+        _ => fallback,
+    }
+}
+
+pub fn run (options: &super::Options) -> bool {
+    //! Runs `fe daemon`: watches `options.search_dir` and answers queries about it until killed.
+    // Searches that follow symbolic links or change what's ignored don't ask the daemon, so it
+    // doesn't either.
+    let options = &super::Options {
+        follow: false,
+        no_ignore: false,
+        no_ignore_vcs: false,
+        ignore_files: ignore::IGNORE_FILES.iter().map(|name| name.to_string()).collect(),
+        ignore_file_case_insensitive: false,
+        overrides: vec![],
+        ..options.clone()
+    };
+    let root = match path::Path::new(&options.search_dir).canonicalize() {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("fe: can't watch {}: {}", options.search_dir, e);
+            return false;
+        },
+    };
+    let socket_path = socket_path(&root);
+    if UnixStream::connect(&socket_path).is_ok() {
+        eprintln!("fe: a daemon is already running for {}", root.display());
+        return false;
+    }
+    // Left behind by a daemon that was killed.
+    let _ = fs::remove_file(&socket_path);
+    if let Some(dir) = socket_path.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+
+    let inotify = match Inotify::new() {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("fe: can't watch {}: {}", root.display(), e);
+            return false;
+        },
+    };
+    let table = Mutex::new(Table {
+        search_dir: path::PathBuf::from(&options.search_dir),
+        dirs: HashMap::new(),
+        watches: HashMap::new(),
+        sources: vec![],
+        inotify: &inotify,
+        options,
+    });
+    table.lock().unwrap().rescan_all();
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(l) => l,
+        Err(e) => {
+            eprintln!("fe: can't listen on {}: {}", socket_path.display(), e);
+            return false;
+        },
+    };
+    {
+        let table = table.lock().unwrap();
+        println!("Watching {} directories in {}, listening on {}", table.dirs.len(), root.display(), socket_path.display());
+    }
+
+    let (table, root) = (&table, &root);
+    thread::scope(|scope| {
+        scope.spawn(|| watch(&inotify, table));
+        // Each connection gets its own thread, so a slow client doesn't hold up the others.
+        for stream in listener.incoming() {
+            match stream {
+                Ok(s) => {
+                    scope.spawn(move || if let Err(e) = serve(s, table, root, options) {
+                        v(format!("Failed to answer a query: {}", e), options);
+                    });
+                },
+                Err(e) => v(format!("Failed to accept a connection: {}", e), options),
+            }
+        }
+    });
+    true
+}
+
+fn watch (inotify: &Inotify, table: &Mutex<Table>) {
+    loop {
+        let mut events = match inotify.read(-1) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("fe: stopped watching for changes: {}", e);
+                return;
+            },
+        };
+        // More changes usually follow the first one.
+        while let Ok(more) = inotify.read(SETTLE_TIME_MS) {
+            if more.is_empty() {
+                break;
+            }
+            events.extend(more);
+        }
+        table.lock().unwrap().apply(events);
+    }
+}
+
+fn serve (stream: UnixStream, table: &Mutex<Table>, root: &path::Path, options: &super::Options) -> io::Result<()> {
+    //! Answers one query, see the README for the protocol.
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    let mut line = String::new();
+    io::BufReader::new((&stream).take(MAX_QUERY_LENGTH)).read_line(&mut line)?;
+    let mut writer = io::BufWriter::new(&stream);
+    if !line.ends_with('\n') && line.len() as u64 == MAX_QUERY_LENGTH {
+        return writeln!(writer, "{{\"error\":{}}}", json::string(&format!("the query is longer than {} bytes", MAX_QUERY_LENGTH)));
+    }
+    let request = match json::parse(&line) {
+        Ok(r) => r,
+        Err(e) => return writeln!(writer, "{{\"error\":{}}}", json::string(&format!("the query isn't valid JSON: {}", e))),
+    };
+    vv(format!("Query: {}", line.trim()), options);
+
+    let (matches, count) = match answer(&request, table, root, options) {
+        Ok(answered) => answered,
+        Err(e) => {
+            writeln!(writer, "{{\"error\":{}}}", json::string(&e))?;
+            return writer.flush();
+        },
+    };
+    for (relative, matched) in matches {
+        let positions: Vec<String> = matched.positions.iter().map(|p| p.to_string()).collect();
+        let path_field = match relative.to_str() {
+            Some(s) => format!("\"path\":{}", json::string(s)),
+            None => {
+                let bytes: Vec<String> = relative.as_os_str().as_bytes().iter().map(|b| b.to_string()).collect();
+                format!("\"path_bytes\":[{}]", bytes.join(","))
+            },
+        };
+        writeln!(writer, "{{{},\"score\":{},\"positions\":[{}]}}", path_field, matched.score, positions.join(","))?;
+    }
+    writeln!(writer, "{{\"done\":true,\"matches\":{}}}", count)?;
+    writer.flush()
+}
+
+fn answer (request: &Json, table: &Mutex<Table>, root: &path::Path, options: &super::Options) -> Result<(Vec<(path::PathBuf, Match)>, usize), String> {
+    //! The best `limit` matches for `request`, with paths relative to its `prefix`, and how many
+    //! matches there are in all. The table is only locked while matching.
+    if request.get("root").and_then(Json::as_str) != Some(&root.to_string_lossy()) {
+        return Err(format!("this daemon is for {}", root.display()));
+    }
+    let pattern = request.get("pattern").and_then(Json::as_str).ok_or("the query has no pattern")?;
+    let flag = |name| request.get(name).and_then(Json::as_bool).unwrap_or(false);
+    let mut query_options = options.clone();
+    query_options.search_type = match request.get("search_type").and_then(Json::as_str) {
+        None | Some("fuzzy") => super::SearchType::Fuzzy,
+        Some("regex") => super::SearchType::Regex,
+        Some("exact") => super::SearchType::Exact,
+        Some(t) => return Err(format!("unknown search_type {}", t)),
+    };
+    query_options.insensitive = flag("insensitive");
+    query_options.smart_case = false;
+    query_options.search_names_only = flag("names_only");
+    query_options.word_boundaries = if flag("separator_words") {
+        super::WordBoundaries::Separators
+    } else {
+        super::WordBoundaries::CamelCase
+    };
+    let limit = request.get("limit").and_then(Json::as_f64).map_or(usize::MAX, |l| l as usize);
+
+    let prefix = path::PathBuf::from(request.get("prefix").and_then(Json::as_str).unwrap_or("./"));
+
+    let mut table = table.lock().unwrap();
+    if current_sources(&table.search_dir, options) != table.sources {
+        v(String::from("Ignore files outside of the root changed, listing everything again."), options);
+        table.rescan_all();
+    }
+    // Paths are matched as the querying `fe` would print them, under its search directory. That's
+    // usually the daemon's own, then the paths in the table are matched as they are.
+    let same_prefix = prefix.as_os_str() == table.search_dir.as_os_str();
+    let paths = table.paths().filter_map(|p| match p.strip_prefix(&table.search_dir) {
+        Ok(_) if same_prefix => Some(Cow::Borrowed(p)),
+        Ok(relative) => Some(Cow::Owned(prefix.join(relative))),
+        Err(_) => None,
+    });
+    let matches = find::match_all(pattern, &query_options, paths)?;
+    let count = matches.len();
+    let best = matches.into_iter()
+        .take(limit)
+        .map(|(path, matched)| (path.strip_prefix(&prefix).unwrap_or(&path).to_path_buf(), matched))
+        .collect();
+    Ok((best, count))
+}
+
+pub fn query (pattern: &str, options: &super::Options) -> Option<Vec<(path::PathBuf, Match)>> {
+    //! Asks a daemon running for `options.search_dir`, if there is one, for the paths matching
+    //! `pattern`. Returns `None` if there isn't one or it couldn't answer.
+    let root = path::Path::new(&options.search_dir).canonicalize().ok()?;
+    let socket_path = socket_path(&root);
+    let mut stream = match UnixStream::connect(&socket_path) {
+        Ok(s) => s,
+        Err(e) => {
+            vv(format!("No daemon at {:?}: {}", socket_path, e), options);
+            return None;
+        },
+    };
+    let search_type = match options.search_type {
+        super::SearchType::Fuzzy => "fuzzy",
+        super::SearchType::Regex => "regex",
+        super::SearchType::Exact => "exact",
+    };
+    let separator_words = match options.word_boundaries {
+        super::WordBoundaries::Separators => true,
+        super::WordBoundaries::CamelCase => false,
+    };
+    let request = format!("{{\"root\":{},\"prefix\":{},\"pattern\":{},\"search_type\":\"{}\",\"insensitive\":{},\"names_only\":{},\"separator_words\":{}}}\n",
+        json::string(&root.to_string_lossy()),
+        json::string(&options.search_dir),
+        json::string(pattern),
+        search_type,
+        find::is_insensitive(pattern, options),
+        options.search_names_only,
+        separator_words);
+    let sent = stream.set_read_timeout(Some(QUERY_TIMEOUT)).and_then(|_| stream.write_all(request.as_bytes()));
+    if let Err(e) = sent {
+        v(format!("Failed to ask the daemon at {:?}: {}", socket_path, e), options);
+        return None;
+    }
+
+    let search_dir = path::Path::new(&options.search_dir);
+    let mut matches = vec![];
+    for line in io::BufReader::new(stream).lines() {
+        let response = match line.map_err(|e| e.to_string()).and_then(|l| json::parse(&l)) {
+            Ok(r) => r,
+            Err(e) => {
+                v(format!("Failed to read the daemon's answer: {}", e), options);
+                return None;
+            },
+        };
+        if let Some(e) = response.get("error").and_then(Json::as_str) {
+            v(format!("The daemon couldn't answer: {}", e), options);
+            return None;
+        }
+        if response.get("done").is_some() {
+            v(format!("The daemon at {:?} found {} matches.", socket_path, matches.len()), options);
+            return Some(matches);
+        }
+        let relative = match (response.get("path").and_then(Json::as_str), response.get("path_bytes").and_then(Json::as_array)) {
+            (Some(p), _) => path::PathBuf::from(p),
+            (None, Some(bytes)) => {
+                let bytes: Vec<u8> = bytes.iter().filter_map(Json::as_f64).map(|b| b as u8).collect();
+                path::PathBuf::from(OsStr::from_bytes(&bytes))
+            },
+            (None, None) => continue,
+        };
+        matches.push((search_dir.join(relative), Match {
+            score: response.get("score").and_then(Json::as_f64).unwrap_or(0.0) as i64,
+            positions: response.get("positions")
+                .and_then(Json::as_array)
+                .map(|p| p.iter().filter_map(Json::as_f64).map(|p| p as usize).collect())
+                .unwrap_or_default(),
+        }));
+    }
+    v(String::from("The daemon stopped before it finished answering."), options);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::ffi::OsStringExt;
+
+    const ROOT: &str = "/home/me/project";
+
+    // The paths answered with their matched positions, and how many matched in all.
+    type Answer = (Vec<(String, Vec<usize>)>, usize);
+
+    fn table<'a> (inotify: &'a Inotify, options: &'a super::super::Options) -> Table<'a> {
+        //! A table of the search directory `./` as if it had been scanned, without reading it.
+        let search_dir = path::PathBuf::from("./");
+        let rules = Arc::new(ignore::RuleSet::new_default(&search_dir, options));
+        let entry = |path: &str, is_dir: bool, ignored: bool| (search_dir.join(path), is_dir, ignored);
+        let mut dirs = HashMap::new();
+        dirs.insert(search_dir.clone(), WatchedDir {
+            wd: None,
+            parent_rules: rules.clone(),
+            entries: vec![entry("src", true, false), entry("target", true, true), entry("Cargo.toml", false, false)],
+        });
+        dirs.insert(search_dir.join("src"), WatchedDir {
+            wd: None,
+            parent_rules: rules.clone(),
+            entries: vec![
+                entry("src/main.rs", false, false),
+                entry("src/mounts.rs", false, false),
+                entry("src/find.rs", false, false),
+                (search_dir.join(OsString::from_vec(b"src/m\xe9.rs".to_vec())), false, false),
+            ],
+        });
+        Table {
+            sources: current_sources(&search_dir, options),
+            search_dir,
+            dirs,
+            watches: HashMap::new(),
+            inotify,
+            options,
+        }
+    }
+
+    fn ask (query: &str) -> Result<Answer, String> {
+        let options = super::super::Options::for_tests();
+        let inotify = Inotify::new().unwrap();
+        let table = Mutex::new(table(&inotify, &options));
+        let request = json::parse(query).unwrap();
+        answer(&request, &table, path::Path::new(ROOT), &options).map(|(matches, count)| {
+            let matches = matches.into_iter().map(|(p, m)| (p.to_string_lossy().into_owned(), m.positions)).collect();
+            (matches, count)
+        })
+    }
+
+    fn paths (query: &str) -> Vec<String> {
+        ask(query).unwrap().0.into_iter().map(|(p, _)| p).collect()
+    }
+
+    #[test]
+    fn fuzzy_queries () {
+        let (matches, count) = ask(r#"{"root":"/home/me/project","pattern":"mrs"}"#).unwrap();
+        assert_eq!(count, 3);
+        // The name that isn't UTF-8 is the shortest.
+        assert_eq!(matches[0], (String::from("src/m\u{fffd}.rs"), vec![4, 7, 8]));
+        assert_eq!(matches[1], (String::from("src/main.rs"), vec![4, 9, 10]));
+        assert_eq!(matches[2].0, "src/mounts.rs");
+        // Ignored paths are never answered.
+        assert!(paths(r#"{"root":"/home/me/project","pattern":"target"}"#).is_empty());
+    }
+
+    #[test]
+    fn limits () {
+        let (matches, count) = ask(r#"{"root":"/home/me/project","pattern":"mrs","limit":2}"#).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[1].0, "src/main.rs");
+    }
+
+    #[test]
+    fn prefixes () {
+        // Paths are matched under the querying fe's search directory and answered relative to it.
+        let (matches, _) = ask(r#"{"root":"/home/me/project","prefix":"../project/","pattern":"projmain"}"#).unwrap();
+        assert_eq!(matches, vec![(String::from("src/main.rs"), vec![3, 4, 5, 6, 15, 16, 17, 18])]);
+        assert_eq!(paths(r#"{"root":"/home/me/project","prefix":"./","pattern":"main"}"#), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn search_types () {
+        assert_eq!(paths(r#"{"root":"/home/me/project","pattern":"^src/f","search_type":"regex"}"#), vec!["src/find.rs"]);
+        assert_eq!(paths(r#"{"root":"/home/me/project","pattern":"cargo.toml","search_type":"exact"}"#), Vec::<String>::new());
+        assert_eq!(paths(r#"{"root":"/home/me/project","pattern":"cargo.toml","search_type":"exact","insensitive":true}"#), vec!["Cargo.toml"]);
+        // Only file names are searched, so `src` matches nothing in it.
+        assert_eq!(paths(r#"{"root":"/home/me/project","pattern":"srcmain","names_only":true}"#), Vec::<String>::new());
+        assert_eq!(paths(r#"{"root":"/home/me/project","pattern":"src","names_only":true}"#), vec!["src"]);
+    }
+
+    #[test]
+    fn bad_queries () {
+        assert_eq!(ask(r#"{"root":"/elsewhere","pattern":"a"}"#).unwrap_err(), "this daemon is for /home/me/project");
+        assert_eq!(ask(r#"{"root":"/home/me/project"}"#).unwrap_err(), "the query has no pattern");
+        assert_eq!(ask(r#"{"root":"/home/me/project","pattern":"a","search_type":"glob"}"#).unwrap_err(), "unknown search_type glob");
+        assert!(ask(r#"{"root":"/home/me/project","pattern":"(","search_type":"regex"}"#).is_err());
+    }
+
+    fn serve_line (line: Vec<u8>) -> String {
+        //! What the daemon writes back to a client sending `line`.
+        let options = super::super::Options::for_tests();
+        let inotify = Inotify::new().unwrap();
+        let table = Mutex::new(table(&inotify, &options));
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let writer = thread::spawn(move || {
+            // The daemon stops reading long lines, so this can fail.
+            let _ = client.write_all(&line);
+            // The daemon closes the connection without reading the rest of a long line, which can
+            // reset it after the answer arrived.
+            let mut response = String::new();
+            let _ = client.read_to_string(&mut response);
+            response
+        });
+        serve(server, &table, path::Path::new(ROOT), &options).unwrap();
+        writer.join().unwrap()
+    }
+
+    #[test]
+    fn protocol () {
+        assert_eq!(serve_line(b"{\"root\":\"/home/me/project\",\"pattern\":\"Catom\"}\n".to_vec()),
+                   "{\"path\":\"Cargo.toml\",\"score\":294,\"positions\":[0,1,6,7,8]}\n{\"done\":true,\"matches\":1}\n");
+        assert_eq!(serve_line(b"{\"root\":\"/home/me/project\",\"pattern\":\"m\\u00e9\"}\n".to_vec()).lines().next(),
+                   Some("{\"path_bytes\":[115,114,99,47,109,233,46,114,115],\"score\":111,\"positions\":[4,5]}"));
+        assert_eq!(serve_line(b"{\"pattern\":\n".to_vec()), "{\"error\":\"the query isn't valid JSON: unexpected end\"}\n");
+    }
+
+    #[test]
+    fn oversized_queries () {
+        let mut long = b"{\"root\":\"/home/me/project\",\"pattern\":\"".to_vec();
+        long.resize(MAX_QUERY_LENGTH as usize * 4, b'a');
+        assert_eq!(serve_line(long), "{\"error\":\"the query is longer than 65536 bytes\"}\n");
+        let deep = format!("{}\n", "[".repeat(1000)).into_bytes();
+        assert_eq!(serve_line(deep), "{\"error\":\"the query isn't valid JSON: arrays and objects are nested more than 32 deep\"}\n");
+    }
+}
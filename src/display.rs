@@ -1,49 +1,243 @@
+use std::io;
+use std::io::Write;
 use std::path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use term_painter::ToStyle;
+use term_painter::Style;
+use term_painter::Color;
 use term_painter::Color::*;
 use term_painter::Attr::*;
 use fileinfo::FileInfo;
-use std::ffi::OsStr;
+use json;
+use name::Name;
 use log::*;
+use super::OutputFormat;
 
-pub fn print_as_path(path: &path::Path, options: &super::Options) {
-    let mut s = path.to_str().unwrap_or("");
-    if s.starts_with("./") {
-        s = &s[2..];
+// Whether a path has been printed yet, JSON arrays need commas between their objects.
+static PRINTED_ANY: AtomicBool = AtomicBool::new(false);
+
+pub fn print_start(options: &super::Options) {
+    if options.output == OutputFormat::Json {
+        println!("[");
     }
-    print_path(path, s, options);
 }
 
-pub fn print_as_filename(path: &path::Path, options: &super::Options) {
-    let s = path.file_name().unwrap_or(OsStr::new("")).to_str().unwrap_or("");
-    print_path(path, s, options);
+pub fn print_end(options: &super::Options) {
+    if options.output == OutputFormat::Json {
+        if PRINTED_ANY.load(Ordering::Relaxed) {
+            println!();
+        }
+        println!("]");
+    }
+}
+
+pub fn print_as_path(path: &path::Path, info: Option<&FileInfo>, positions: &[usize], options: &super::Options) {
+    print_path(path, &Name::from_path(path), info, positions, options);
+}
+
+pub fn print_as_filename(path: &path::Path, info: Option<&FileInfo>, options: &super::Options) {
+    print_path(path, &Name::from_file_name(path), info, &[], options);
 }
 
-pub fn print_path(path: &path::Path, s: &str, options: &super::Options) {
+pub fn print_path(path: &path::Path, name: &Name, info: Option<&FileInfo>, positions: &[usize], options: &super::Options) {
+    //! Prints `name` coloured by the type of file at `path`. The characters at `positions`, the
+    //! character indices that matched the search, are also made bold and underlined. If `info`
+    //! wasn't already fetched by whoever found `path` it is fetched here. Bytes in `name` that
+    //! aren't valid UTF-8 are escaped, unless `options.raw_names` asks for the bytes as they are.
+    let first = !PRINTED_ANY.swap(true, Ordering::Relaxed);
+    match options.output {
+        OutputFormat::Text => (),
+        OutputFormat::Print0 => {
+            print_raw(name, b"\0");
+            return;
+        },
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            print_json(path, name, info, positions, first, options);
+            return;
+        },
+    }
+    if options.raw_names {
+        print_raw(name, b"\n");
+        return;
+    }
+    let s = name.escaped();
     if options.no_colors {
         println!("{}", s);
         return;
     }
 
-    let info = match FileInfo::new(path) {
-        Ok(i) => i,
+    let style = file_style(path, info, options);
+    if positions.is_empty() {
+        println!("{}", style.paint(s));
+        return;
+    }
+    print_highlighted(&name.escaped_chars(), positions, style);
+}
+
+fn file_style(path: &path::Path, info: Option<&FileInfo>, options: &super::Options) -> Style {
+    //! The style paths are printed in, based on the type and permissions of the file.
+    let fetched;
+    let info = match info {
+        Some(i) => Ok(i),
+        None => {
+            fetched = FileInfo::new(path);
+            fetched.as_ref().map_err(|e| e.to_string())
+        },
+    };
+    match info {
+        Ok(info) => {
+            if info.is_dir() {
+                Blue.to_style()
+            } else if info.is_symbolic_link() {
+                Magenta.to_style()
+            } else if info.everyone_can_do_everything() {
+                Plain.bg(Green).fg(Black)
+            } else if info.is_executable() {
+                Red.to_style()
+            } else {
+                Plain.to_style()
+            }
+        },
         Err(e) => {
-            v(format!("Error getting metadata for {}: {}", s, e), options);
-            println!("{}", Plain.bg(Red).fg(White).paint(s));
-            return;
+            v(format!("Error getting metadata for {:?}: {}", path, e), options);
+            Plain.bg(Red).fg(White)
         }
-    };
+    }
+}
+
+pub fn ansi_path(path: &path::Path, positions: &[usize], max_chars: usize, options: &super::Options) -> String {
+    //! Returns the path as `print_as_path` would print it, with ANSI escape codes for its style,
+    //! for writing somewhere other than stdout. It's cut off after `max_chars` characters.
+    let name = Name::from_path(path);
+    let style = if options.no_colors { Plain.to_style() } else { file_style(path, None, options) };
+    let highlight = style.bold().underline();
+    let mut s = String::new();
+    let mut positions = positions.iter().peekable();
+    let mut run_highlighted = None;
+    for (i, c) in name.escaped_chars().iter().enumerate().take(max_chars) {
+        let highlighted = positions.peek() == Some(&&i);
+        if highlighted {
+            positions.next();
+        }
+        if run_highlighted != Some(highlighted) {
+            s.push_str(&ansi_codes(if highlighted { highlight } else { style }));
+            run_highlighted = Some(highlighted);
+        }
+        s.push_str(c);
+    }
+    s.push_str("\x1b[0m");
+    s
+}
 
-    if info.is_dir() {
-        println!("{}", Blue.paint(s));
-    } else if info.is_symbolic_link() {
-        println!("{}", Magenta.paint(s));
-    } else if info.everyone_can_do_everything() {
-        println!("{}", Plain.bg(Green).fg(Black).paint(s));
-    } else if info.is_executable() {
-        println!("{}", Red.paint(s));
+fn ansi_codes(style: Style) -> String {
+    fn color_code(color: Color) -> Option<u16> {
+        match color {
+            Color::Black => Some(0),
+            Color::Red => Some(1),
+            Color::Green => Some(2),
+            Color::Yellow => Some(3),
+            Color::Blue => Some(4),
+            Color::Magenta => Some(5),
+            Color::Cyan => Some(6),
+            Color::White => Some(7),
+            _ => None,
+        }
+    }
+    let mut codes = vec![String::from("0")];
+    if let Some(true) = style.get_bold() {
+        codes.push(String::from("1"));
+    }
+    if let Some(true) = style.get_underline() {
+        codes.push(String::from("4"));
+    }
+    if let Some(c) = color_code(style.fg) {
+        codes.push((30 + c).to_string());
+    }
+    if let Some(c) = color_code(style.bg) {
+        codes.push((40 + c).to_string());
+    }
+    format!("\x1b[{}m", codes.join(";"))
+}
+
+fn print_highlighted(chars: &[String], positions: &[usize], style: Style) {
+    let highlight = style.bold().underline();
+    let mut positions = positions.iter().peekable();
+    let mut run = String::new();
+    let mut run_highlighted = false;
+    for (i, c) in chars.iter().enumerate() {
+        let highlighted = positions.peek() == Some(&&i);
+        if highlighted {
+            positions.next();
+        }
+        if highlighted != run_highlighted && !run.is_empty() {
+            print!("{}", if run_highlighted { highlight } else { style }.paint(&run));
+            run.clear();
+        }
+        run_highlighted = highlighted;
+        run.push_str(c);
+    }
+    println!("{}", if run_highlighted { highlight } else { style }.paint(&run));
+}
+
+pub fn print_chosen(path: &path::Path, options: &super::Options) {
+    //! Prints a path picked in the interactive picker as the raw bytes it's made of, so whatever
+    //! reads them gets the real path, one per line or NUL terminated with `-0`.
+    let terminator: &[u8] = if options.output == OutputFormat::Print0 { b"\0" } else { b"\n" };
+    print_raw(&Name::from_path(path), terminator);
+}
+
+fn print_raw(name: &Name, terminator: &[u8]) {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let _ = stdout.write_all(&name.bytes).and_then(|_| stdout.write_all(terminator));
+}
+
+fn print_json(path: &path::Path, name: &Name, info: Option<&FileInfo>, positions: &[usize], first: bool, options: &super::Options) {
+    //! Prints an object like `{"path":"src/main.rs","kind":"file","size":2048,"mode":420,"mtime":1485000000,"positions":[4,9]}`.
+    //! `positions` are the character indices in `path` that matched the search. Names that aren't
+    //! UTF-8 have their invalid bytes escaped as `\xNN` in `path`, and the raw bytes in a
+    //! `path_bytes` array after it. If the file can't be stat'ed `kind`, `size`, `mode` and
+    //! `mtime` are null.
+    let fetched;
+    let info = match info {
+        Some(i) => Some(i),
+        None => {
+            fetched = FileInfo::new(path);
+            if let Err(ref e) = fetched {
+                v(format!("Error getting metadata for {}: {}", name.escaped(), e), options);
+            }
+            fetched.as_ref().ok()
+        },
+    };
+    let null = || String::from("null");
+    let positions: Vec<String> = name.escaped_positions(positions).iter().map(|p| p.to_string()).collect();
+    let path_bytes = if name.is_utf8() {
+        String::new()
     } else {
-        println!("{}", s);
+        let bytes: Vec<String> = name.bytes.iter().map(|b| b.to_string()).collect();
+        format!(",\"path_bytes\":[{}]", bytes.join(","))
+    };
+    let object = format!("{{\"path\":{}{},\"kind\":{},\"size\":{},\"mode\":{},\"mtime\":{},\"positions\":[{}]}}",
+        json::string(&name.escaped()),
+        path_bytes,
+        info.map_or_else(null, |i| json::string(i.kind())),
+        info.map_or_else(null, |i| i.size().to_string()),
+        info.map_or_else(null, |i| i.permissions().to_string()),
+        info.and_then(|i| i.modified()).map_or_else(null, |m| m.to_string()),
+        positions.join(","));
+    if options.output == OutputFormat::Ndjson {
+        println!("{}", object);
+    } else if first {
+        print!("  {}", object);
+    } else {
+        print!(",\n  {}", object);
+    }
+}
+
+pub fn print_stats(counters: &[(&str, usize)]) {
+    // Printed to stderr so stats don't end up in a pipeline with the results.
+    for &(name, count) in counters {
+        eprintln!("  {:>12}    {}", count, name);
     }
 }
 
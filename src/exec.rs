@@ -0,0 +1,296 @@
+use std::ffi::OsString;
+use std::io;
+use std::io::Write;
+use std::mem;
+use std::path;
+use std::process;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use display;
+
+// Batches are split into more than one command if their arguments get longer than this, to stay
+// well under the operating system's limit on argument length.
+const MAX_BATCH_ARGS_LENGTH: usize = 128 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct CommandTemplate {
+    // The command and its arguments, which may contain placeholders for the path.
+    args: Vec<String>,
+    // Run the command once with all paths instead of once per path.
+    batch: bool,
+}
+
+impl CommandTemplate {
+
+    pub fn new (args: Vec<String>, batch: bool) -> CommandTemplate {
+        CommandTemplate {
+            args,
+            batch,
+        }
+    }
+
+    pub fn is_batch (&self) -> bool {
+        self.batch
+    }
+
+    fn has_placeholder (arg: &str) -> bool {
+        ["{}", "{/}", "{//}", "{.}", "{/.}"].iter().any(|p| arg.contains(p))
+    }
+
+    fn expand (arg: &str, path: &path::Path) -> OsString {
+        //! Replaces the placeholders in `arg`:
+        //!
+        //!     * `{}` the path.
+        //!     * `{/}` the path's file name.
+        //!     * `{//}` the path's parent directory.
+        //!     * `{.}` the path without its extension.
+        //!     * `{/.}` the path's file name without its extension.
+        //!
+        let mut expanded = OsString::new();
+        let mut rest = arg;
+        while let Some(start) = rest.find('{') {
+            expanded.push(&rest[..start]);
+            rest = &rest[start..];
+            let (replacement, len) = if rest.starts_with("{}") {
+                (path.as_os_str().to_os_string(), 2)
+            } else if rest.starts_with("{/}") {
+                (path.file_name().unwrap_or(path.as_os_str()).to_os_string(), 3)
+            } else if rest.starts_with("{//}") {
+                let parent = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| path::Path::new("."));
+                (parent.as_os_str().to_os_string(), 4)
+            } else if rest.starts_with("{.}") {
+                (path.with_extension("").into_os_string(), 3)
+            } else if rest.starts_with("{/.}") {
+                (path.file_stem().unwrap_or(path.as_os_str()).to_os_string(), 4)
+            } else {
+                (OsString::from("{"), 1)
+            };
+            expanded.push(replacement);
+            rest = &rest[len..];
+        }
+        expanded.push(rest);
+        expanded
+    }
+
+    fn command (&self, paths: &[path::PathBuf]) -> process::Command {
+        //! Builds the command for `paths`. Arguments with placeholders are repeated for each path,
+        //! and if there aren't any the paths are added to the end.
+        let mut command = process::Command::new(&self.args[0]);
+        let mut has_placeholder = false;
+        for arg in &self.args[1..] {
+            if !CommandTemplate::has_placeholder(arg) {
+                command.arg(arg);
+                continue;
+            }
+            has_placeholder = true;
+            for path in paths {
+                command.arg(CommandTemplate::expand(arg, path));
+            }
+        }
+        if !has_placeholder {
+            command.args(paths);
+        }
+        command
+    }
+}
+
+fn run (mut command: process::Command, very_verbose: bool) -> bool {
+    //! Runs `command`, then prints all of its output at once so output from commands running at the
+    //! same time isn't interleaved.
+    if very_verbose {
+        display::print_debug_message(&format!("Running {:?}", command));
+    }
+    match command.stdin(process::Stdio::null()).output() {
+        Ok(output) => {
+            let _ = io::stdout().lock().write_all(&output.stdout);
+            let _ = io::stderr().lock().write_all(&output.stderr);
+            output.status.success()
+        },
+        Err(e) => {
+            eprintln!("fe: failed to run {:?}: {}", command.get_program(), e);
+            false
+        },
+    }
+}
+
+fn run_batch (mut command: process::Command, very_verbose: bool) -> bool {
+    //! Runs a `--exec-batch` command with fe's standard input and output, so it can be interactive
+    //! like an editor or pager, or stream its output. Only one runs at a time.
+    if very_verbose {
+        display::print_debug_message(&format!("Running {:?}", command));
+    }
+    match command.status() {
+        Ok(status) => status.success(),
+        Err(e) => {
+            eprintln!("fe: failed to run {:?}: {}", command.get_program(), e);
+            false
+        },
+    }
+}
+
+pub struct Executor<'a> {
+    template: &'a CommandTemplate,
+    // Paths waiting to be run in one command for `--exec-batch`.
+    batch: Vec<path::PathBuf>,
+    // Sends paths to the threads running `--exec` commands.
+    sender: Option<mpsc::Sender<path::PathBuf>>,
+    workers: Vec<thread::JoinHandle<bool>>,
+    options: &'a super::Options,
+}
+
+impl<'a> Executor<'a> {
+
+    pub fn new (template: &'a CommandTemplate, options: &'a super::Options) -> Executor<'a> {
+        let mut executor = Executor {
+            template,
+            batch: vec![],
+            sender: None,
+            workers: vec![],
+            options,
+        };
+        if template.batch {
+            return executor;
+        }
+
+        // Commands for each path are run on a pool of threads that take paths from a shared receiver.
+        let (sender, receiver) = mpsc::channel::<path::PathBuf>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..options.threads.max(1) {
+            let receiver = receiver.clone();
+            let template = CommandTemplate::new(template.args.clone(), false);
+            let very_verbose = options.very_verbose;
+            executor.workers.push(thread::spawn(move || {
+                let mut success = true;
+                loop {
+                    let path = match receiver.lock().unwrap().recv() {
+                        Ok(p) => p,
+                        Err(_) => return success,
+                    };
+                    success &= run(template.command(&[path]), very_verbose);
+                }
+            }));
+        }
+        executor.sender = Some(sender);
+        executor
+    }
+
+    pub fn add (&mut self, path: &path::Path) {
+        let path = path.strip_prefix(".").unwrap_or(path).to_path_buf();
+        match self.sender {
+            Some(ref sender) => {
+                // Workers only stop once the sender is dropped in `finish`.
+                let _ = sender.send(path);
+            },
+            None => self.batch.push(path),
+        }
+    }
+
+    pub fn finish (mut self) -> bool {
+        //! Waits for all commands to finish and returns whether every one of them succeeded.
+        let mut success = true;
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            success &= worker.join().unwrap_or(false);
+        }
+
+        for batch in batches(mem::take(&mut self.batch), MAX_BATCH_ARGS_LENGTH) {
+            success &= run_batch(self.template.command(&batch), self.options.very_verbose);
+        }
+        success
+    }
+}
+
+fn batches (paths: Vec<path::PathBuf>, max_length: usize) -> Vec<Vec<path::PathBuf>> {
+    //! Splits `paths` into batches whose paths add up to at most `max_length` bytes, counting one
+    //! more for each to separate them. A path longer than that gets a batch of its own.
+    let mut batches = vec![];
+    let mut batch = vec![];
+    let mut length = 0;
+    for path in paths {
+        let path_length = path.as_os_str().len() + 1;
+        if !batch.is_empty() && length + path_length > max_length {
+            batches.push(mem::take(&mut batch));
+            length = 0;
+        }
+        length += path_length;
+        batch.push(path);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+
+    fn expand (arg: &str, path: &str) -> OsString {
+        CommandTemplate::expand(arg, path::Path::new(path))
+    }
+
+    fn args (template: &[&str], paths: &[&str]) -> Vec<String> {
+        let template = CommandTemplate::new(template.iter().map(|a| a.to_string()).collect(), true);
+        let paths: Vec<path::PathBuf> = paths.iter().map(path::PathBuf::from).collect();
+        let command = template.command(&paths);
+        let mut args = vec![command.get_program().to_string_lossy().into_owned()];
+        args.extend(command.get_args().map(|a| a.to_string_lossy().into_owned()));
+        args
+    }
+
+    #[test]
+    fn placeholders () {
+        assert_eq!(expand("{}", "src/main.rs"), "src/main.rs");
+        assert_eq!(expand("{/}", "src/main.rs"), "main.rs");
+        assert_eq!(expand("{//}", "src/main.rs"), "src");
+        assert_eq!(expand("{.}", "src/main.rs"), "src/main");
+        assert_eq!(expand("{/.}", "src/main.rs"), "main");
+        assert_eq!(expand("{/.}", "src/archive.tar.gz"), "archive.tar");
+        assert_eq!(expand("--out={//}/{/.}.o", "src/main.rs"), "--out=src/main.o");
+        assert_eq!(expand("{{}}", "a"), "{a}");
+    }
+
+    #[test]
+    fn placeholders_without_directories_or_extensions () {
+        assert_eq!(expand("{//}", "main.rs"), ".");
+        assert_eq!(expand("{.}", "Makefile"), "Makefile");
+        assert_eq!(expand("{/.}", ".gitignore"), ".gitignore");
+        assert_eq!(expand("{/}", "/"), "/");
+        assert_eq!(expand("{x} {/ } {", "a"), "{x} {/ } {");
+    }
+
+    #[test]
+    fn names_that_are_not_utf8 () {
+        use std::os::unix::ffi::OsStrExt;
+        let path = path::Path::new(OsStr::from_bytes(b"dir/caf\xe9.txt"));
+        assert_eq!(CommandTemplate::expand("{/.}", path), OsStr::from_bytes(b"caf\xe9"));
+    }
+
+    #[test]
+    fn commands () {
+        // Without placeholders the paths are added to the end.
+        assert_eq!(args(&["wc", "-l"], &["a", "b"]), vec!["wc", "-l", "a", "b"]);
+        // Arguments with placeholders are repeated for each path, others stay as they are.
+        assert_eq!(args(&["cp", "{}", "-t", "out/{//}"], &["x/a", "b"]), vec!["cp", "x/a", "b", "-t", "out/x", "out/."]);
+        assert_eq!(args(&["echo", "{/.}"], &["x/a.rs"]), vec!["echo", "a"]);
+    }
+
+    #[test]
+    fn batch_splitting () {
+        let paths = |names: &[&str]| names.iter().map(path::PathBuf::from).collect::<Vec<_>>();
+        // Each path counts one more byte for the separator.
+        assert_eq!(batches(paths(&["aaa", "bbb", "ccc"]), 8), vec![paths(&["aaa", "bbb"]), paths(&["ccc"])]);
+        assert_eq!(batches(paths(&["aaa", "bbb", "ccc"]), 7), vec![paths(&["aaa"]), paths(&["bbb"]), paths(&["ccc"])]);
+        assert_eq!(batches(paths(&["aaa", "bbb", "ccc"]), 12), vec![paths(&["aaa", "bbb", "ccc"])]);
+        // Paths that are too long on their own still run.
+        assert_eq!(batches(paths(&["a", "much/too/long", "b"]), 4), vec![paths(&["a"]), paths(&["much/too/long"]), paths(&["b"])]);
+        assert!(batches(vec![], 8).is_empty());
+
+        let many: Vec<path::PathBuf> = (0..100_000).map(|i| path::PathBuf::from(format!("path/{:06}", i))).collect();
+        let split = batches(many.clone(), MAX_BATCH_ARGS_LENGTH);
+        assert_eq!(split.len(), 10);
+        assert!(split.iter().all(|b| b.iter().map(|p| p.as_os_str().len() + 1).sum::<usize>() <= MAX_BATCH_ARGS_LENGTH));
+        assert_eq!(split.concat(), many);
+    }
+}
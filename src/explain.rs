@@ -0,0 +1,202 @@
+use std::fs;
+use std::iter;
+use std::os::unix::fs::MetadataExt;
+use std::path;
+use display;
+use fileinfo::FileInfo;
+use find;
+use ignore;
+use mounts;
+
+pub fn explain(target: &str, pattern: &str, options: &super::Options) -> bool {
+    //! Prints why searching for `pattern` would or wouldn't find `target`: the ignore file lines
+    //! that ignore or re-include it or the directories it's in, the `--hidden`, `--prune`, depth,
+    //! file system and symbolic link limits that stop the walk before it, how much of the pattern
+    //! matches it and which filter it fails. Returns false if `target` isn't in the search
+    //! directory.
+    let search_dir = path::Path::new(&options.search_dir);
+    let relative = match relative_path(search_dir, path::Path::new(target)) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("fe: can't explain {}: {}", target, e);
+            return false;
+        },
+    };
+    if relative.as_os_str().is_empty() {
+        println!("{} is the search directory, which isn't a result.", target);
+        return true;
+    }
+    let path = search_dir.join(&relative);
+    let components: Vec<path::Component> = relative.components().collect();
+
+    // Walk down to the target like a search would, checking each directory on the way.
+    let file_systems = mounts::FileSystems::new(search_dir, options);
+    let mut rule_set = ignore::RuleSet::new_root(search_dir, options);
+    let mut dir = search_dir.to_path_buf();
+    for (i, component) in components.iter().enumerate() {
+        let depth = i + 1;
+        let current = dir.join(component);
+        let shown: path::PathBuf = components[..depth].iter().collect();
+        let name = current.file_name().unwrap_or_default();
+        rule_set = with_ignore_files(rule_set, &dir, options);
+        let is_link = fs::symlink_metadata(&current).is_ok_and(|m| m.file_type().is_symlink());
+        let is_dir = if is_link && !options.follow {
+            false
+        } else {
+            current.metadata().is_ok_and(|m| m.is_dir())
+        };
+        let inside = if depth < components.len() { ", so nothing in it is searched" } else { "" };
+
+        if !options.show_hidden && find::is_hidden(name) {
+            println!("{} is hidden{}. --hidden searches hidden paths.", shown.display(), inside);
+            return true;
+        }
+        match rule_set.decide(&current, is_dir) {
+            Some(ref d) if d.ignored => {
+                println!("{}: {} ignores {}{}.", d.source, d.rule, shown.display(), inside);
+                return true;
+            },
+            Some(d) => println!("{}: {} re-includes {}.", d.source, d.rule, shown.display()),
+            None => (),
+        }
+        if depth == components.len() {
+            break;
+        }
+
+        if !is_dir {
+            if is_link {
+                println!("{} is a symbolic link, which isn't searched unless -L or --follow is given.", shown.display());
+            } else {
+                println!("{} isn't a directory.", shown.display());
+            }
+            return true;
+        }
+        if find::is_pruned(&current, options) {
+            println!("{} is pruned by --prune, so nothing in it is searched.", shown.display());
+            return true;
+        }
+        if let Some(max) = options.max_depth.filter(|max| depth >= *max) {
+            println!("{} is at depth {}, --max-depth {} stops searching there.", shown.display(), depth, max);
+            return true;
+        }
+        if file_systems.is_active() && current.metadata().is_ok_and(|m| !file_systems.allows(&current, m.dev(), options)) {
+            println!("{} is on a file system --one-file-system, --fs-type or --exclude-fs-type doesn't search.", shown.display());
+            return true;
+        }
+        dir = current;
+    }
+
+    // The target is reached, what's left is whether it's printed.
+    let depth = components.len();
+    if depth < options.min_depth {
+        println!("{} is at depth {}, --min-depth {} doesn't print it.", relative.display(), depth, options.min_depth);
+        return true;
+    }
+    if options.max_depth.is_some_and(|max| depth > max) {
+        println!("{} is at depth {}, deeper than --max-depth allows.", relative.display(), depth);
+        return true;
+    }
+    if !options.print_pruned && path.is_dir() && find::is_pruned(&path, options) {
+        println!("{} is pruned by --prune, --print-pruned prints it.", relative.display());
+        return true;
+    }
+    if !explain_match(pattern, &path, options) {
+        return true;
+    }
+
+    if !options.types.is_empty() || !options.filters.is_empty() {
+        let info = match FileInfo::new(&path).or_else(|_| FileInfo::without_following(&path)) {
+            Ok(info) => info,
+            Err(e) => {
+                println!("Can't get the file info of {} to filter it: {}", relative.display(), e);
+                return true;
+            },
+        };
+        if !info.is_any_of(&path, &options.types) {
+            println!("{} isn't of a type --type asks for.", relative.display());
+            return true;
+        }
+        if !options.filters.matches(&info) {
+            println!("{} doesn't pass --size, --changed-within, --changed-before, --newer or --perm.", relative.display());
+            return true;
+        }
+    }
+    if options.expression.as_ref().is_some_and(|expression| !expression.matches(&mut find::Candidate::new(&path, options))) {
+        println!("{} doesn't match the --where expression.", relative.display());
+        return true;
+    }
+    println!("{} is found.", relative.display());
+    true
+}
+
+fn relative_path(search_dir: &path::Path, target: &path::Path) -> Result<path::PathBuf, String> {
+    //! The path of `target` in the search directory. Only the directories it's in are resolved, a
+    //! symbolic link is explained as itself.
+    let root = search_dir.canonicalize().map_err(|e| format!("{}: {}", search_dir.display(), e))?;
+    let canonical = match (target.parent(), target.file_name()) {
+        (Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { path::Path::new(".") } else { parent };
+            parent.canonicalize().map(|p| p.join(name))
+        },
+        _ => target.canonicalize(),
+    };
+    let canonical = canonical
+        .and_then(|c| fs::symlink_metadata(&c).map(|_| c))
+        .map_err(|e| e.to_string())?;
+    canonical.strip_prefix(&root)
+        .map(path::Path::to_path_buf)
+        .map_err(|_| format!("it isn't in the search directory {}", search_dir.display()))
+}
+
+fn with_ignore_files(rule_set: ignore::RuleSet, dir: &path::Path, options: &super::Options) -> ignore::RuleSet {
+    //! Adds the rules of the ignore files in `dir` like a search does.
+    ignore::ignore_file_names(options).into_iter().fold(rule_set, |rules, name| {
+        match ignore::RuleSet::extend(&rules, &dir.join(name), options) {
+            Ok(extended) => extended,
+            Err(_) => rules,
+        }
+    })
+}
+
+fn explain_match(pattern: &str, path: &path::Path, options: &super::Options) -> bool {
+    //! Prints how `pattern` matches `path` and returns whether it does. Fuzzy patterns that don't
+    //! match are shown as far as they got.
+    if pattern.is_empty() {
+        return true;
+    }
+    let target = if options.search_names_only {
+        format!("the file name of {}", path.display())
+    } else {
+        path.display().to_string()
+    };
+    let matched = |pattern: &str| match find::match_all(pattern, options, iter::once(path)) {
+        Ok(mut matches) => matches.pop().map(|(_, m)| m),
+        Err(_) => None,
+    };
+    if let Some(m) = matched(pattern) {
+        println!("{} matches:", pattern);
+        display::print_as_path(path, None, &m.positions, options);
+        return true;
+    }
+    match options.search_type {
+        super::SearchType::Regex => println!("The regular expression {} doesn't match {}.", pattern, target),
+        super::SearchType::Exact => println!("{} isn't exactly {}.", pattern, target),
+        super::SearchType::Fuzzy => {
+            // Matching more of a pattern only ever fails where matching less didn't.
+            let partial = pattern.char_indices()
+                .skip(1)
+                .map(|(i, _)| &pattern[..i])
+                .map_while(|prefix| matched(prefix).map(|m| (prefix, m)))
+                .last();
+            match partial {
+                Some((prefix, m)) => {
+                    let next = pattern[prefix.len()..].chars().next().unwrap_or_default();
+                    println!("Fuzzy matching gets as far as {}, then {} doesn't continue a word or start a later one in {}:", prefix, next, target);
+                    display::print_as_path(path, None, &m.positions, options);
+                },
+                None => println!("No word in {} starts with the first character of {}.", target, pattern),
+            }
+        },
+    }
+    false
+}
@@ -0,0 +1,454 @@
+use std::ffi::OsStr;
+use std::time::SystemTime;
+use fileinfo::FileType;
+use filter;
+use filter::PermFilter;
+use find;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+
+    fn holds<T: PartialOrd>(self, a: T, b: T) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+
+    fn flipped(self) -> Comparison {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Predicate {
+    // `path:`, `path=`, `path~`, `name:`, `name=` and `name~`, matched like the search pattern.
+    Path(Box<find::Matcher>),
+    // `ext:rs`, the extension without its dot.
+    Extension(String),
+    Type(FileType),
+    // `size>10M`, sizes that are equal are compared rounded up to the unit they were given in.
+    Size {
+        comparison: Comparison,
+        size: u64,
+        unit: u64,
+    },
+    // `mtime<7d` or `mtime<2024-01-31`. Durations were turned into times, so `<` became `>`.
+    Modified(Comparison, SystemTime),
+    Perm(PermFilter),
+}
+
+// A parsed `--where` expression.
+#[derive(Debug)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Predicate(Predicate),
+}
+
+impl Expr {
+
+    pub fn matches(&self, candidate: &mut find::Candidate) -> bool {
+        //! Whether the path of `candidate` matches. Its file info is only fetched if a predicate
+        //! that's evaluated needs it.
+        match *self {
+            Expr::And(ref a, ref b) => a.matches(candidate) && b.matches(candidate),
+            Expr::Or(ref a, ref b) => a.matches(candidate) || b.matches(candidate),
+            Expr::Not(ref e) => !e.matches(candidate),
+            Expr::Predicate(ref p) => p.matches(candidate),
+        }
+    }
+}
+
+impl Predicate {
+
+    fn matches(&self, candidate: &mut find::Candidate) -> bool {
+        let path = candidate.path;
+        if let Predicate::Path(ref matcher) = *self {
+            return matcher.is_match(path);
+        }
+        if let Predicate::Extension(ref extension) = *self {
+            let insensitive = find::is_insensitive(extension, candidate.options);
+            return match path.extension().map(OsStr::to_string_lossy) {
+                Some(e) if insensitive => e.to_lowercase() == extension.to_lowercase(),
+                Some(e) => e == extension.as_str(),
+                None => false,
+            };
+        }
+        let info = match candidate.info() {
+            Some(info) => info,
+            None => return false,
+        };
+        match *self {
+            Predicate::Type(file_type) => info.is_any_of(path, &[file_type]),
+            Predicate::Size { comparison: Comparison::Equal, size, unit } => info.size().div_ceil(unit) * unit == size,
+            Predicate::Size { comparison, size, .. } => comparison.holds(info.size(), size),
+            Predicate::Modified(comparison, time) => info.modified_time().is_some_and(|modified| comparison.holds(modified, time)),
+            Predicate::Perm(ref perm) => perm.matches(info.permissions()),
+            Predicate::Path(_) | Predicate::Extension(_) => unreachable!(),
+        }
+    }
+}
+
+pub struct ParseError {
+    // Character index of where in the expression the error is.
+    pub column: usize,
+    pub message: String,
+}
+
+struct Parser<'a> {
+    chars: Vec<char>,
+    at: usize,
+    now: SystemTime,
+    options: &'a super::Options,
+}
+
+pub fn parse(source: &str, options: &super::Options) -> Result<Expr, ParseError> {
+    //! Parses a `--where` expression like `ext:rs and mtime<7d and not path:tests/ or name=Cargo.toml`.
+    //! `not` binds tightest, then `and`, then `or`, and parentheses group. Predicates are a field,
+    //! an operator and a value:
+    //!
+    //!     * `path` and `name` with `:` for a fuzzy match, `=` for an exact one or `~` for a regular expression.
+    //!     * `ext` with `:` or `=`, the extension without its dot.
+    //!     * `type` with `:` or `=`, a type like `--type` takes.
+    //!     * `size` with `<`, `<=`, `=`, `>=` or `>`, a size like `--size` takes without its sign.
+    //!     * `mtime` with `<`, `<=`, `>=` or `>`, a duration like `7d` compared with how long ago
+    //!         the path was modified, or a date compared with when it was.
+    //!     * `perm` with `:` or `=`, permissions like `--perm` takes.
+    //!
+    //! Values with spaces or parentheses in them can be quoted with `'` or `"`.
+    let mut parser = Parser {
+        chars: source.chars().collect(),
+        at: 0,
+        now: SystemTime::now(),
+        options,
+    };
+    let expr = parser.or()?;
+    parser.skip_whitespace();
+    if parser.at < parser.chars.len() {
+        return Err(parser.error("expected and, or or the end of the expression"));
+    }
+    Ok(expr)
+}
+
+impl<'a> Parser<'a> {
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError {
+            column: self.at,
+            message: String::from(message),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.at).is_some_and(|c| c.is_whitespace()) {
+            self.at += 1;
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        //! Consumes `keyword` if it's next, as a word of its own.
+        self.skip_whitespace();
+        let end = self.at + keyword.chars().count();
+        if end > self.chars.len() || !self.chars[self.at..end].iter().cloned().eq(keyword.chars()) {
+            return false;
+        }
+        if self.chars.get(end).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+            return false;
+        }
+        self.at = end;
+        true
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.skip_whitespace();
+        if self.chars.get(self.at) == Some(&'(') {
+            self.at += 1;
+            let expr = self.or()?;
+            self.skip_whitespace();
+            if self.chars.get(self.at) != Some(&')') {
+                return Err(self.error("expected )"));
+            }
+            self.at += 1;
+            return Ok(expr);
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<Expr, ParseError> {
+        let field_start = self.at;
+        while self.chars.get(self.at).is_some_and(|c| c.is_alphanumeric() || *c == '_') {
+            self.at += 1;
+        }
+        let field: String = self.chars[field_start..self.at].iter().collect();
+        if field.is_empty() {
+            return Err(self.error("expected a field like path, name, ext, type, size, mtime or perm"));
+        }
+
+        self.skip_whitespace();
+        let comparison_start = self.at;
+        let operator: String = self.chars[self.at..].iter().take_while(|c| "=:~<>".contains(**c)).take(2).collect();
+        let comparison = match operator.as_str() {
+            "<" => Comparison::Less,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            ">" => Comparison::Greater,
+            "=" | ":" | "~" => Comparison::Equal,
+            _ => return Err(self.error(&format!("expected an operator like :, =, ~, < or > after {}", field))),
+        };
+        self.at += operator.chars().count();
+
+        self.skip_whitespace();
+        let value_start = self.at;
+        let value = self.value()?;
+        let at_value = |message: String| ParseError {
+            column: value_start,
+            message,
+        };
+        let only = |operators: &str| if operators.split(' ').any(|o| o == operator) {
+            Ok(())
+        } else {
+            Err(ParseError {
+                column: comparison_start,
+                message: format!("{} can only be compared with {}", field, operators),
+            })
+        };
+
+        let predicate = match field.as_str() {
+            "path" | "name" => {
+                only(": = ~")?;
+                let search_type = match operator.as_str() {
+                    ":" => super::SearchType::Fuzzy,
+                    "=" => super::SearchType::Exact,
+                    _ => super::SearchType::Regex,
+                };
+                let matcher = find::Matcher::new(&value, search_type, field == "name", self.options).map_err(at_value)?;
+                Predicate::Path(Box::new(matcher))
+            },
+            "ext" => {
+                only(": =")?;
+                Predicate::Extension(value.trim_start_matches('.').to_string())
+            },
+            "type" => {
+                only(": =")?;
+                let file_type = FileType::parse(&value).ok_or_else(|| at_value(format!("{} isn't a type, use f, d, l, p, s, c, b, x or e", value)))?;
+                Predicate::Type(file_type)
+            },
+            "size" => {
+                only("< <= = >= >")?;
+                let (size, unit) = filter::parse_bytes(&value).map_err(at_value)?;
+                Predicate::Size {
+                    comparison,
+                    size,
+                    unit,
+                }
+            },
+            "mtime" => {
+                // Times are to the second, so one would hardly ever equal `1d` ago.
+                only("< <= >= >")?;
+                let time = filter::parse_time(&value, self.now).map_err(at_value)?;
+                // Durations are how long ago, so an older time is a bigger duration.
+                let comparison = if filter::is_date(&value) { comparison } else { comparison.flipped() };
+                Predicate::Modified(comparison, time)
+            },
+            "perm" => {
+                only(": =")?;
+                Predicate::Perm(filter::parse_perm(&value).map_err(at_value)?)
+            },
+            _ => return Err(ParseError {
+                column: field_start,
+                message: format!("unknown field {}, use path, name, ext, type, size, mtime or perm", field),
+            }),
+        };
+        Ok(Expr::Predicate(predicate))
+    }
+
+    fn value(&mut self) -> Result<String, ParseError> {
+        //! A quoted value, or everything up to whitespace or a parenthesis.
+        match self.chars.get(self.at).cloned() {
+            Some(quote) if quote == '\'' || quote == '"' => {
+                let start = self.at;
+                self.at += 1;
+                let value: String = self.chars[self.at..].iter().take_while(|c| **c != quote).collect();
+                self.at += value.chars().count();
+                if self.at >= self.chars.len() {
+                    self.at = start;
+                    return Err(self.error("this quote is never closed"));
+                }
+                self.at += 1;
+                Ok(value)
+            },
+            _ => {
+                let value: String = self.chars[self.at..].iter().take_while(|c| !c.is_whitespace() && **c != '(' && **c != ')').collect();
+                if value.is_empty() {
+                    return Err(self.error("expected a value"));
+                }
+                self.at += value.chars().count();
+                Ok(value)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn show(expr: &Expr) -> String {
+        //! `expr` with every operator parenthesized and predicates shortened.
+        match *expr {
+            Expr::And(ref a, ref b) => format!("({} and {})", show(a), show(b)),
+            Expr::Or(ref a, ref b) => format!("({} or {})", show(a), show(b)),
+            Expr::Not(ref e) => format!("(not {})", show(e)),
+            Expr::Predicate(Predicate::Path(_)) => "path".to_string(),
+            Expr::Predicate(Predicate::Extension(ref e)) => format!("ext:{}", e),
+            Expr::Predicate(Predicate::Type(t)) => format!("type:{:?}", t),
+            Expr::Predicate(Predicate::Size { comparison, size, unit }) => format!("size {:?} {}/{}", comparison, size, unit),
+            Expr::Predicate(Predicate::Modified(comparison, _)) => format!("mtime {:?}", comparison),
+            Expr::Predicate(Predicate::Perm(_)) => "perm".to_string(),
+        }
+    }
+
+    fn parsed(source: &str) -> String {
+        match parse(source, &super::super::Options::for_tests()) {
+            Ok(expr) => show(&expr),
+            Err(e) => panic!("{} at column {} of {}", e.message, e.column, source),
+        }
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        match parse(source, &super::super::Options::for_tests()) {
+            Ok(expr) => panic!("{} parsed as {}", source, show(&expr)),
+            Err(e) => (e.column, e.message),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parsed("ext:a or ext:b and ext:c"), "(ext:a or (ext:b and ext:c))");
+        assert_eq!(parsed("ext:a and ext:b or ext:c"), "((ext:a and ext:b) or ext:c)");
+        assert_eq!(parsed("not ext:a and ext:b"), "((not ext:a) and ext:b)");
+        assert_eq!(parsed("not not ext:a or ext:b"), "((not (not ext:a)) or ext:b)");
+        assert_eq!(parsed("ext:a and ext:b and ext:c"), "((ext:a and ext:b) and ext:c)");
+        assert_eq!(parsed("ext:a or ext:b or ext:c"), "((ext:a or ext:b) or ext:c)");
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(parsed("(ext:a or ext:b) and ext:c"), "((ext:a or ext:b) and ext:c)");
+        assert_eq!(parsed("not (ext:a or ext:b)"), "(not (ext:a or ext:b))");
+        assert_eq!(parsed("((ext:a))"), "ext:a");
+        assert_eq!(parsed("ext:a and(ext:b)"), "(ext:a and ext:b)");
+        assert_eq!(parsed("  ( ext:a )  "), "ext:a");
+    }
+
+    #[test]
+    fn keywords_are_whole_words() {
+        assert_eq!(parsed("not ext:not"), "(not ext:not)");
+        assert_eq!(parsed("ext:and or ext:or"), "(ext:and or ext:or)");
+        assert_eq!(error("ext:a andext:b"), (6, "expected and, or or the end of the expression".to_string()));
+        assert_eq!(error("nothing:x").1, "unknown field nothing, use path, name, ext, type, size, mtime or perm");
+    }
+
+    #[test]
+    fn predicates() {
+        assert_eq!(parsed("ext=.rs"), "ext:rs");
+        assert_eq!(parsed("type:d"), "type:Directory");
+        assert_eq!(parsed("size>=1k"), "size GreaterOrEqual 1024/1024");
+        assert_eq!(parsed("size = 2"), "size Equal 2/1");
+        assert_eq!(parsed("perm:-644"), "perm");
+        assert_eq!(parsed("path:src and name=main.rs and name~^m"), "((path and path) and path)");
+    }
+
+    #[test]
+    fn mtime_durations_flip() {
+        // Modified less than 7 days ago is modified after 7 days ago.
+        assert_eq!(parsed("mtime<7d"), "mtime Greater");
+        assert_eq!(parsed("mtime>=1h"), "mtime LessOrEqual");
+        assert_eq!(error("mtime=1d"), (5, "mtime can only be compared with < <= >= >".to_string()));
+        // Dates are compared as they are.
+        assert_eq!(parsed("mtime<2024-01-31"), "mtime Less");
+        assert_eq!(parsed("mtime>'2024-01-31 12:00'"), "mtime Greater");
+
+        let before = SystemTime::now();
+        let expr = parse("mtime<7d", &super::super::Options::for_tests()).ok().unwrap();
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        match expr {
+            Expr::Predicate(Predicate::Modified(_, time)) => assert!(time <= before - week + Duration::from_secs(60) && time >= before - week),
+            _ => panic!("mtime<7d isn't an mtime predicate"),
+        }
+    }
+
+    #[test]
+    fn quoting() {
+        let options = super::super::Options::for_tests();
+        let matches = |source: &str, path: &str| match parse(source, &options) {
+            Ok(Expr::Predicate(Predicate::Path(matcher))) => matcher.is_match(Path::new(path)),
+            _ => panic!("{} isn't a path predicate", source),
+        };
+        assert!(matches("name='my file.txt'", "docs/my file.txt"));
+        assert!(matches("name=\"a (b)\"", "a (b)"));
+        assert!(matches("name=\"it's\"", "it's"));
+        assert!(!matches("name='my file.txt'", "my"));
+        assert_eq!(parsed("name='x' and ext:'a b'"), "(path and ext:a b)");
+        // Parentheses end unquoted values.
+        assert_eq!(error("ext:rs)"), (6, "expected and, or or the end of the expression".to_string()));
+    }
+
+    #[test]
+    fn error_columns() {
+        assert_eq!(error(""), (0, "expected a field like path, name, ext, type, size, mtime or perm".to_string()));
+        assert_eq!(error("ext:rs and"), (10, "expected a field like path, name, ext, type, size, mtime or perm".to_string()));
+        assert_eq!(error("ext:rs ext:md"), (7, "expected and, or or the end of the expression".to_string()));
+        assert_eq!(error("(ext:rs"), (7, "expected )".to_string()));
+        assert_eq!(error("colour:red"), (0, "unknown field colour, use path, name, ext, type, size, mtime or perm".to_string()));
+        assert_eq!(error("ext rs"), (4, "expected an operator like :, =, ~, < or > after ext".to_string()));
+        assert_eq!(error("ext<rs"), (3, "ext can only be compared with : =".to_string()));
+        assert_eq!(error("size:1k"), (4, "size can only be compared with < <= = >= >".to_string()));
+        assert_eq!(error("ext:"), (4, "expected a value".to_string()));
+        assert_eq!(error("ext:rs or name='x"), (15, "this quote is never closed".to_string()));
+        assert_eq!(error("type:q").0, 5);
+        assert_eq!(error("mtime < soon").0, 8);
+        // Columns count characters, not bytes.
+        assert_eq!(error("name:é and ext:rs ext:md").0, 18);
+    }
+}
@@ -1,10 +1,45 @@
 use std::path;
 use std::fs;
 use std::io;
+use std::time;
+use stats;
 
 #[allow(non_camel_case_types)]
 type mode_t = u32;
 
+// The kinds of files `--type` selects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    File,
+    Directory,
+    Symlink,
+    Pipe,
+    Socket,
+    CharDevice,
+    BlockDevice,
+    // Executable and empty narrow the other types down instead of adding to them.
+    Executable,
+    Empty,
+}
+
+impl FileType {
+
+    pub fn parse(s: &str) -> Option<FileType> {
+        match s {
+            "f" | "file" => Some(FileType::File),
+            "d" | "directory" => Some(FileType::Directory),
+            "l" | "symlink" => Some(FileType::Symlink),
+            "p" | "pipe" => Some(FileType::Pipe),
+            "s" | "socket" => Some(FileType::Socket),
+            "c" | "char-device" => Some(FileType::CharDevice),
+            "b" | "block-device" => Some(FileType::BlockDevice),
+            "x" | "executable" => Some(FileType::Executable),
+            "e" | "empty" => Some(FileType::Empty),
+            _ => None,
+        }
+    }
+}
+
 pub struct FileInfo {
     metadata: fs::Metadata,
     mode: mode_t,
@@ -15,14 +50,11 @@ pub struct FileInfo {
 // and from https://github.com/rust-lang/libc/blob/cb7f66732175e6171587ed69656b7aae7dd2e6ec/src/unix/bsd/apple/mod.rs
 // as well as from https://www.gnu.org/software/libc/manual/html_node/Permission-Bits.html
 
-// TODO: support these?
-#[allow(dead_code)]
 const S_IFIFO: mode_t = 4096; // FIFO special.
-#[allow(dead_code)]
 const S_IFCHR: mode_t = 8192; // Character special.
-#[allow(dead_code)]
+const S_IFDIR: mode_t = 16384; // Directory.
 const S_IFBLK: mode_t = 24576; // Block special
-#[allow(dead_code)]
+const S_IFREG: mode_t = 32768; // Regular.
 const S_IFSOCK: mode_t = 49152; // Socket
 
 
@@ -34,23 +66,128 @@ const S_IRWXG: mode_t = 56; // Read, write, execute/search by group.
 const S_IXGRP: mode_t = 8; // Execute/search permission, group.
 const S_IRWXO: mode_t = 7; // Read, write, execute/search by others.
 const S_IXOTH: mode_t = 1; // Execute/search permission, others.
+const S_PERMISSIONS: mode_t = 4095; // Permission bits, including set-user-ID, set-group-ID and sticky.
 
 impl FileInfo {
 
     pub fn new(path: &path::Path) -> Result<FileInfo, io::Error> {
         let metadata = path.metadata()?;
         let l_metadata = path.symlink_metadata()?;
+        stats::add(&stats::STAT_CALLS, 2);
+        Ok(FileInfo::from_metadata(metadata, &l_metadata))
+    }
+
+    pub fn from_entry(dir_entry: &fs::DirEntry) -> Result<FileInfo, io::Error> {
+        //! `DirEntry::metadata` doesn't follow symbolic links, so unlike `new` this only has to stat
+        //! a second time when the entry is a link.
+        let l_metadata = dir_entry.metadata()?;
+        if !l_metadata.file_type().is_symlink() {
+            stats::add(&stats::STAT_CALLS, 1);
+            stats::add(&stats::STAT_CALLS_SAVED, 1);
+            return Ok(FileInfo::from_metadata(l_metadata.clone(), &l_metadata));
+        }
+        let metadata = dir_entry.path().metadata()?;
+        stats::add(&stats::STAT_CALLS, 2);
+        Ok(FileInfo::from_metadata(metadata, &l_metadata))
+    }
+
+    pub fn without_following(path: &path::Path) -> Result<FileInfo, io::Error> {
+        //! The info of a symbolic link itself, for links that can't be followed because what they
+        //! point to is gone.
+        let l_metadata = path.symlink_metadata()?;
+        stats::add(&stats::STAT_CALLS, 1);
+        Ok(FileInfo::from_metadata(l_metadata.clone(), &l_metadata))
+    }
+
+    pub fn from_metadata(metadata: fs::Metadata, l_metadata: &fs::Metadata) -> FileInfo {
+        //! Creates a `FileInfo` from metadata that was already fetched, `metadata` following
+        //! symbolic links and `l_metadata` not.
         let mode = FileInfo::mode(&metadata);
-        let l_mode = FileInfo::mode(&l_metadata);
-        return Ok(FileInfo {
-            metadata: metadata,
-            mode: mode,
-            l_mode: l_mode,
-        });
+        let l_mode = FileInfo::mode(l_metadata);
+        FileInfo {
+            metadata,
+            mode,
+            l_mode,
+        }
     }
 
     pub fn is_symbolic_link(&self) -> bool {
-        (self.l_mode & S_IFMT) & S_IFLNK == S_IFLNK
+        self.l_mode & S_IFMT == S_IFLNK
+    }
+
+    pub fn is_type(&self, file_type: FileType) -> bool {
+        //! Whether the file itself, not what it links to, is of `file_type`. Executable and empty
+        //! aren't types of their own, see `is_executable` and `is_empty`.
+        let l_type = self.l_mode & S_IFMT;
+        match file_type {
+            FileType::File => !matches!(l_type, S_IFLNK | S_IFDIR | S_IFIFO | S_IFSOCK | S_IFCHR | S_IFBLK),
+            FileType::Directory => l_type == S_IFDIR,
+            FileType::Symlink => l_type == S_IFLNK,
+            FileType::Pipe => l_type == S_IFIFO,
+            FileType::Socket => l_type == S_IFSOCK,
+            FileType::CharDevice => l_type == S_IFCHR,
+            FileType::BlockDevice => l_type == S_IFBLK,
+            FileType::Executable | FileType::Empty => true,
+        }
+    }
+
+    pub fn is_any_of(&self, path: &path::Path, file_types: &[FileType]) -> bool {
+        //! Whether the file at `path` is one of `file_types`, and executable or empty if those are
+        //! in it too. `[File, Executable]` only matches executable files, `[Empty]` matches empty
+        //! files and directories.
+        let kinds: Vec<FileType> = file_types.iter()
+            .cloned()
+            .filter(|t| *t != FileType::Executable && *t != FileType::Empty)
+            .collect();
+        if !kinds.is_empty() && !kinds.iter().any(|t| self.is_type(*t)) {
+            return false;
+        }
+        // What links point to has to be an executable file, not a directory, socket or nothing.
+        let executable_file = self.mode & S_IFMT == S_IFREG && self.is_executable();
+        if file_types.contains(&FileType::Executable) && !executable_file {
+            return false;
+        }
+        !file_types.contains(&FileType::Empty) || self.is_empty(path)
+    }
+
+    pub fn is_empty(&self, path: &path::Path) -> bool {
+        //! Whether the file at `path` is an empty regular file or an empty directory.
+        match self.l_mode & S_IFMT {
+            S_IFDIR => fs::read_dir(path).map(|mut entries| entries.next().is_none()).unwrap_or(false),
+            S_IFLNK | S_IFIFO | S_IFSOCK | S_IFCHR | S_IFBLK => false,
+            _ => self.size() == 0,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        //! The type of the file itself, not of what it links to.
+        match self.l_mode & S_IFMT {
+            S_IFLNK => "symlink",
+            S_IFDIR => "directory",
+            S_IFIFO => "fifo",
+            S_IFSOCK => "socket",
+            S_IFCHR => "char_device",
+            S_IFBLK => "block_device",
+            _ => "file",
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.metadata.len()
+    }
+
+    pub fn permissions(&self) -> mode_t {
+        self.mode & S_PERMISSIONS
+    }
+
+    pub fn modified(&self) -> Option<u64> {
+        //! Seconds since the unix epoch the file was last modified.
+        let modified = self.modified_time()?;
+        modified.duration_since(time::UNIX_EPOCH).ok().map(|d| d.as_secs())
+    }
+
+    pub fn modified_time(&self) -> Option<time::SystemTime> {
+        self.metadata.modified().ok()
     }
 
     pub fn everyone_can_do_everything (&self) -> bool {
@@ -59,7 +196,7 @@ impl FileInfo {
     }
 
     pub fn is_dir(&self) -> bool {
-        return self.metadata.is_dir();
+        self.metadata.is_dir()
     }
 
     pub fn is_executable(&self) -> bool {
@@ -0,0 +1,392 @@
+use libc;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use fileinfo::FileInfo;
+
+// `--size`, in bytes. Exact sizes are compared in the unit they were given in, rounded up like
+// find(1) does, so `10k` matches files from 9k up to 10k.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SizeFilter {
+    Over(u64),
+    Under(u64),
+    Exactly {
+        size: u64,
+        unit: u64,
+    },
+}
+
+// `--perm`, permission bits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PermFilter {
+    Exactly(u32),
+    // All of the bits are set, `-644`.
+    All(u32),
+    // Any of the bits is set, `/111`.
+    Any(u32),
+}
+
+// Filters on file metadata. Every filter that's set has to match for a path to be printed. They
+// need a path's file info, so paths are only stat'ed for them when there are any.
+#[derive(Debug, Clone, Default)]
+pub struct Filters {
+    pub sizes: Vec<SizeFilter>,
+    pub changed_after: Option<SystemTime>,
+    pub changed_before: Option<SystemTime>,
+    pub perm: Option<PermFilter>,
+}
+
+impl PermFilter {
+
+    pub fn matches (&self, permissions: u32) -> bool {
+        match *self {
+            PermFilter::Exactly(bits) => permissions == bits,
+            PermFilter::All(bits) => permissions & bits == bits,
+            PermFilter::Any(bits) => bits == 0 || permissions & bits != 0,
+        }
+    }
+}
+
+impl Filters {
+
+    pub fn is_empty (&self) -> bool {
+        self.sizes.is_empty() && self.changed_after.is_none() && self.changed_before.is_none() && self.perm.is_none()
+    }
+
+    pub fn matches (&self, info: &FileInfo) -> bool {
+        let size = info.size();
+        let size_matches = |filter: &SizeFilter| match *filter {
+            SizeFilter::Over(limit) => size > limit,
+            SizeFilter::Under(limit) => size < limit,
+            SizeFilter::Exactly { size: expected, unit } => size.div_ceil(unit) * unit == expected,
+        };
+        if !self.sizes.iter().all(size_matches) {
+            return false;
+        }
+        if self.changed_after.is_some() || self.changed_before.is_some() {
+            let modified = match info.modified_time() {
+                Some(m) => m,
+                None => return false,
+            };
+            if self.changed_after.is_some_and(|after| modified <= after) || self.changed_before.is_some_and(|before| modified >= before) {
+                return false;
+            }
+        }
+        self.perm.as_ref().is_none_or(|perm| perm.matches(info.permissions()))
+    }
+}
+
+pub fn parse_size (s: &str) -> Result<SizeFilter, String> {
+    //! Parses sizes like `+10M`, `-2k` or `100`: a number with an optional unit, `b` for bytes or
+    //! `k`, `M`, `G` or `T` for powers of 1024, optionally followed by `B` or `iB`. `+` means
+    //! bigger than, `-` smaller than.
+    let (sign, rest) = match s.chars().next() {
+        Some(c) if c == '+' || c == '-' => (Some(c), &s[1..]),
+        _ => (None, s),
+    };
+    let (size, unit) = parse_bytes(rest)?;
+    Ok(match sign {
+        Some('+') => SizeFilter::Over(size),
+        Some(_) => SizeFilter::Under(size),
+        None => SizeFilter::Exactly {
+            size,
+            unit,
+        },
+    })
+}
+
+pub fn parse_bytes (s: &str) -> Result<(u64, u64), String> {
+    //! Parses a size without a sign, returns it in bytes along with the unit it was given in.
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let number: u64 = s[..digits].parse().map_err(|_| format!("{} isn't a size, like 10M or 2k", s))?;
+    let unit = match s[digits..].to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(format!("unknown size unit {} in {}, use b, k, M, G or T", &s[digits..], s)),
+    };
+    let size = number.checked_mul(unit).ok_or_else(|| format!("{} is too big", s))?;
+    Ok((size, unit))
+}
+
+pub fn parse_perm (s: &str) -> Result<PermFilter, String> {
+    //! Parses permissions like find(1): `644` for exactly those, `-644` for at least those and
+    //! `/111` for any of those. Instead of octal they can be symbolic like chmod(1)'s, `u+x` or
+    //! `u=rw,go=r`, starting from no permissions.
+    let (bits, filter): (&str, fn(u32) -> PermFilter) = match s.chars().next() {
+        Some('-') => (&s[1..], PermFilter::All),
+        Some('/') => (&s[1..], PermFilter::Any),
+        _ => (s, PermFilter::Exactly),
+    };
+    match u32::from_str_radix(bits, 8).ok().or_else(|| parse_symbolic_perm(bits)) {
+        Some(bits) if bits <= 0o7777 => Ok(filter(bits)),
+        _ => Err(format!("{} isn't permissions, like 644, -644, /111 or u+x", s)),
+    }
+}
+
+fn parse_symbolic_perm (s: &str) -> Option<u32> {
+    //! Parses comma separated clauses like `ug+rw`, who they're for followed by one or more
+    //! operators and permissions. No one given means everyone.
+    let mut bits = 0;
+    for clause in s.split(',') {
+        let operators = clause.find(['+', '-', '='])?;
+        let mut who = 0;
+        for c in clause[..operators].chars() {
+            who |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        if who == 0 {
+            who = 0o7777;
+        }
+        let mut rest = clause[operators..].chars().peekable();
+        while let Some(operator) = rest.next() {
+            let mut perms = 0;
+            while let Some(&c) = rest.peek() {
+                perms |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    '+' | '-' | '=' => break,
+                    _ => return None,
+                };
+                rest.next();
+            }
+            match operator {
+                '+' => bits |= perms & who,
+                '-' => bits &= !(perms & who),
+                _ => bits = bits & !who | perms & who,
+            }
+        }
+    }
+    Some(bits)
+}
+
+pub fn parse_time (s: &str, now: SystemTime) -> Result<SystemTime, String> {
+    //! Parses a point in time, either a duration before `now` like `2d`, `1h30m` or `90s`, or a
+    //! date like `2024-01-31`, `2024-01-31 12:00` or `2024-01-31T12:00:00`, in local time unless
+    //! it ends with `Z`.
+    if is_date(s) {
+        return parse_date(s);
+    }
+    let ago = parse_duration(s)?;
+    now.checked_sub(ago).ok_or_else(|| format!("{} is too long ago", s))
+}
+
+pub fn is_date (s: &str) -> bool {
+    //! Whether `s` looks like a date rather than a duration.
+    s.len() >= 10 && s.as_bytes()[..4].iter().all(u8::is_ascii_digit) && s.as_bytes()[4] == b'-'
+}
+
+fn parse_duration (s: &str) -> Result<Duration, String> {
+    let invalid = || format!("{} isn't a duration or date, like 2d, 1h30m or 2024-01-31", s);
+    let mut seconds: u64 = 0;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let number: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let letters = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit = match &rest[..letters] {
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "h" | "hour" | "hours" => 60 * 60,
+            "d" | "day" | "days" => 24 * 60 * 60,
+            "w" | "week" | "weeks" => 7 * 24 * 60 * 60,
+            "y" | "year" | "years" => 365 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        rest = &rest[letters..];
+        seconds = number.checked_mul(unit).and_then(|n| seconds.checked_add(n)).ok_or_else(invalid)?;
+    }
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+fn parse_date (s: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("{} isn't a date, like 2024-01-31, 2024-01-31 12:00 or 2024-01-31T12:00:00", s);
+    let (s, utc) = match s.strip_suffix('Z') {
+        Some(local) => (local, true),
+        None => (s, false),
+    };
+    let (date, time) = match s.find([' ', 'T']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let numbers = |part: &str, separator| -> Result<Vec<i32>, String> {
+        part.split(separator).map(|n| n.parse().map_err(|_| invalid())).collect()
+    };
+    let date = numbers(date, '-')?;
+    let time = match time {
+        Some(time) => numbers(time, ':')?,
+        None => vec![],
+    };
+    if date.len() != 3 || time.len() == 1 || time.len() > 3 {
+        return Err(invalid());
+    }
+    let (year, month, day) = (date[0], date[1], date[2]);
+    let (hour, minute, second) = (time.first().cloned().unwrap_or(0), time.get(1).cloned().unwrap_or(0), time.get(2).cloned().unwrap_or(0));
+    if year < 1970 || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month - 1;
+    tm.tm_mday = day;
+    tm.tm_hour = hour;
+    tm.tm_min = minute;
+    tm.tm_sec = second;
+    // Let mktime work out whether daylight saving time applies.
+    tm.tm_isdst = -1;
+    let seconds = unsafe { if utc { libc::timegm(&mut tm) } else { libc::mktime(&mut tm) } };
+    if seconds < 0 {
+        return Err(invalid());
+    }
+    Ok(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+fn days_in_month (year: i32, month: i32) -> i32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sizes () {
+        assert_eq!(parse_size("+10M"), Ok(SizeFilter::Over(10 << 20)));
+        assert_eq!(parse_size("-2k"), Ok(SizeFilter::Under(2048)));
+        assert_eq!(parse_size("100"), Ok(SizeFilter::Exactly { size: 100, unit: 1 }));
+        assert_eq!(parse_size("10KiB"), Ok(SizeFilter::Exactly { size: 10 << 10, unit: 1 << 10 }));
+        assert_eq!(parse_size("1gb"), Ok(SizeFilter::Exactly { size: 1 << 30, unit: 1 << 30 }));
+        assert_eq!(parse_size("3T"), Ok(SizeFilter::Exactly { size: 3 << 40, unit: 1 << 40 }));
+        assert_eq!(parse_size("7b"), Ok(SizeFilter::Exactly { size: 7, unit: 1 }));
+
+        assert_eq!(parse_size(""), Err(String::from(" isn't a size, like 10M or 2k")));
+        assert_eq!(parse_size("+"), Err(String::from(" isn't a size, like 10M or 2k")));
+        assert_eq!(parse_size("k"), Err(String::from("k isn't a size, like 10M or 2k")));
+        assert_eq!(parse_size("10x"), Err(String::from("unknown size unit x in 10x, use b, k, M, G or T")));
+        assert_eq!(parse_size("99999999999T"), Err(String::from("99999999999T is too big")));
+    }
+
+    #[test]
+    fn exact_sizes_round_up () {
+        let dir = super::super::test_dir("exact_sizes_round_up");
+        let filters = Filters {
+            sizes: vec![parse_size("10k").unwrap()],
+            ..Filters::default()
+        };
+        let matches = |size: usize| {
+            let path = dir.join(size.to_string());
+            fs::write(&path, vec![0; size]).unwrap();
+            filters.matches(&FileInfo::new(&path).unwrap())
+        };
+        assert!(!matches(9 * 1024));
+        assert!(matches(9 * 1024 + 1));
+        assert!(matches(10 * 1024));
+        assert!(!matches(10 * 1024 + 1));
+
+        let filters = Filters {
+            sizes: vec![parse_size("+1k").unwrap(), parse_size("-2k").unwrap()],
+            ..Filters::default()
+        };
+        let path = dir.join("between");
+        fs::write(&path, vec![0; 1500]).unwrap();
+        assert!(filters.matches(&FileInfo::new(&path).unwrap()));
+        fs::write(&path, vec![0; 1024]).unwrap();
+        assert!(!filters.matches(&FileInfo::new(&path).unwrap()));
+    }
+
+    #[test]
+    fn octal_perms () {
+        assert_eq!(parse_perm("644"), Ok(PermFilter::Exactly(0o644)));
+        assert_eq!(parse_perm("-644"), Ok(PermFilter::All(0o644)));
+        assert_eq!(parse_perm("/111"), Ok(PermFilter::Any(0o111)));
+        assert_eq!(parse_perm("7777"), Ok(PermFilter::Exactly(0o7777)));
+
+        assert!(PermFilter::Exactly(0o644).matches(0o644));
+        assert!(!PermFilter::Exactly(0o644).matches(0o755));
+        assert!(PermFilter::All(0o644).matches(0o755));
+        assert!(!PermFilter::All(0o111).matches(0o744));
+        assert!(PermFilter::Any(0o111).matches(0o744));
+        assert!(!PermFilter::Any(0o111).matches(0o644));
+        // Like find(1), no bits at all matches everything.
+        assert!(PermFilter::Any(0).matches(0o600));
+    }
+
+    #[test]
+    fn symbolic_perms () {
+        assert_eq!(parse_perm("u+x"), Ok(PermFilter::Exactly(0o100)));
+        assert_eq!(parse_perm("u=rw,go=r"), Ok(PermFilter::Exactly(0o644)));
+        assert_eq!(parse_perm("a=rwx,o-w"), Ok(PermFilter::Exactly(0o775)));
+        assert_eq!(parse_perm("u+rwx-w"), Ok(PermFilter::Exactly(0o500)));
+        assert_eq!(parse_perm("+x"), Ok(PermFilter::Exactly(0o111)));
+        assert_eq!(parse_perm("ug+s,o+t"), Ok(PermFilter::Exactly(0o7000)));
+        assert_eq!(parse_perm("-a+x"), Ok(PermFilter::All(0o111)));
+        assert_eq!(parse_perm("/g+w,o+w"), Ok(PermFilter::Any(0o022)));
+    }
+
+    #[test]
+    fn bad_perms () {
+        for perm in &["", "888", "17777", "-", "/", "u", "z+x", "u+q", "u+x,", "rwxr-xr-x"] {
+            assert_eq!(parse_perm(perm), Err(format!("{} isn't permissions, like 644, -644, /111 or u+x", perm)));
+        }
+    }
+
+    #[test]
+    fn durations () {
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let ago = |s: &str| now.duration_since(parse_time(s, now).unwrap()).unwrap().as_secs();
+        assert_eq!(ago("90s"), 90);
+        assert_eq!(ago("2d"), 2 * 24 * 60 * 60);
+        assert_eq!(ago("1h30m"), 90 * 60);
+        assert_eq!(ago("1week2days"), 9 * 24 * 60 * 60);
+        assert_eq!(ago("0s"), 0);
+        assert!(!is_date("2d"));
+        assert!(!is_date("1h30m"));
+
+        for bad in &["", "2", "d", "2x", "1h30", "-2d", "2 d"] {
+            assert_eq!(parse_time(bad, now), Err(format!("{} isn't a duration or date, like 2d, 1h30m or 2024-01-31", bad)));
+        }
+        assert_eq!(parse_time("99999999999999y", now), Err(String::from("99999999999999y isn't a duration or date, like 2d, 1h30m or 2024-01-31")));
+        assert_eq!(parse_time("300000000000y", now), Err(String::from("300000000000y is too long ago")));
+    }
+
+    #[test]
+    fn dates () {
+        let now = SystemTime::now();
+        let seconds = |s: &str| parse_time(s, now).unwrap().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        assert!(is_date("2024-01-31"));
+        assert!(is_date("2024-01-31 12:00"));
+        assert_eq!(seconds("2024-01-31Z"), 1_706_659_200);
+        assert_eq!(seconds("2024-01-31 12:00Z"), 1_706_659_200 + 12 * 60 * 60);
+        assert_eq!(seconds("2024-01-31T12:00:30Z"), 1_706_659_200 + 12 * 60 * 60 + 30);
+        assert_eq!(seconds("2024-02-29Z"), 1_709_164_800);
+        assert_eq!(seconds("2000-02-29Z"), 951_782_400);
+        // Local time depends on the time zone, it's at most a day off UTC.
+        assert!(seconds("2024-01-31").abs_diff(1_706_659_200) <= 24 * 60 * 60);
+
+        for bad in &["2024-02-31", "2023-02-29", "2100-02-29", "2024-04-31", "2024-01-00", "2024-13-01", "2024-00-10",
+                     "1969-12-31", "2024-01-31 12", "2024-01-31 24:00", "2024-01-31 12:60", "2024-01-31 12:00:00:00",
+                     "2024-01-31T", "2024-1-31x", "2024-01-31ZZ"] {
+            assert_eq!(parse_time(bad, now), Err(format!("{} isn't a date, like 2024-01-31, 2024-01-31 12:00 or 2024-01-31T12:00:00", bad)));
+        }
+    }
+}
@@ -1,36 +1,210 @@
-use regex;
+use regex::bytes;
+use std::cmp;
+use std::ffi::OsStr;
 use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::path;
-use std::io;
+use std::sync::{mpsc, Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use cache;
+use daemon;
+use fileinfo::FileInfo;
+use filter;
 use ignore;
+use index;
+use name::Name;
 use display;
+use exec;
+use stats;
+use walk;
+use mounts;
 use log::*;
 
-fn make_case_insensitive(input: &str, options: &super::Options) -> String {
-    if !options.insensitive {
+// Once more than this many ranked results have been collected they are flushed, best-first, and any
+// further results are printed as they are found so memory doesn't grow without bound on huge trees.
+const MAX_RANKED_RESULTS: usize = 100_000;
+
+// Scoring weights used by `fuzzy_path_match_search`.
+const SCORE_MATCH: i64 = 16;
+const BONUS_WORD_START: i64 = 24;
+const BONUS_CONSECUTIVE: i64 = 32;
+const BONUS_FILENAME: i64 = 16;
+const PENALTY_PATH_CHAR: i64 = 1;
+
+pub fn is_insensitive(pattern: &str, options: &super::Options) -> bool {
+    //! Whether `pattern` is matched case insensitively: always with `--insensitive`, and with
+    //! `--smart-case` unless it has an upper case letter. Escapes like `\W` in regular expressions
+    //! don't count.
+    if options.insensitive {
+        return true;
+    }
+    if !options.smart_case {
+        return false;
+    }
+    let regex = matches!(options.search_type, super::SearchType::Regex);
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' && regex {
+            chars.next();
+        } else if c.is_uppercase() {
+            return false;
+        }
+    }
+    true
+}
+
+fn make_case_insensitive(input: &str, insensitive: bool) -> String {
+    if !insensitive {
         return String::from(input)
     }
-    input.to_lowercase()
+    // Lower case character by character so character indices of matches still line up with the
+    // original string when highlighting them.
+    input.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect()
 }
 
 struct Dir {
     path: path::PathBuf,
+    // How many directories deep it is, the search directory is at depth 0.
+    depth: usize,
+    // The device and inode of each directory it's in when following symbolic links, a link to one
+    // of them would loop forever.
+    ancestors: Vec<(u64, u64)>,
     rule_index: usize,
+    // Whether an ignore file for this directory's parents changed since the walk cache was written.
+    rules_changed: bool,
+}
+
+// A directory entry, read from its directory or from the walk cache.
+struct Entry {
+    path: path::PathBuf,
+    is_dir: bool,
+    // Only entries read from their directory have one.
+    dir_entry: Option<fs::DirEntry>,
+}
+
+pub struct SearchContext<'a> {
+    // The pattern, lower cased if searching case insensitively. Empty to match every path.
+    search: String,
+    // Whether paths are lower cased before matching, see `is_insensitive`.
+    insensitive: bool,
+    regex: Option<&'a bytes::Regex>,
+    // Also give ignored paths to the sink, marked as ignored, without walking ignored directories.
+    include_ignored: bool,
+    cache: Option<&'a cache::WalkCache>,
+    options: &'a super::Options,
 }
 
-struct SearchContext<'a> {
-    search: &'a str,
-    regex: Option<&'a regex::Regex>,
+impl<'a> SearchContext<'a> {
+
+    pub fn fuzzy(pattern: &str, options: &'a super::Options) -> SearchContext<'a> {
+        //! A context for fuzzy matching paths against `pattern` outside of a search, for the
+        //! interactive picker.
+        let insensitive = is_insensitive(pattern, options);
+        SearchContext {
+            search: make_case_insensitive(pattern, insensitive),
+            insensitive,
+            regex: None,
+            include_ignored: false,
+            cache: None,
+            options,
+        }
+    }
+
+    pub fn match_path(&self, path: &path::Path) -> Option<Match> {
+        match_path(self, path, &Name::from_path(path))
+    }
+}
+
+pub struct Match {
+    pub score: i64,
+    // Character indices of the matched characters in the string that was searched, in order.
+    pub positions: Vec<usize>,
+}
+
+struct Hit {
+    path: path::PathBuf,
+    // Fetched while searching when it's needed for printing, to save the printer another stat.
+    info: Option<FileInfo>,
+    matched: Match,
+    ignored: bool,
+}
+
+struct Results<'a> {
+    hits: Vec<Hit>,
+    streaming: bool,
+    // Runs `--exec` and `--exec-batch` commands instead of printing results.
+    executor: Option<exec::Executor<'a>>,
     options: &'a super::Options,
 }
 
+impl<'a> Results<'a> {
+
+    fn new(options: &'a super::Options) -> Results<'a> {
+        // Only fuzzy matches carry a meaningful score, everything else is ordered by path. Commands
+        // run for each result start right away, there's no point in waiting to rank them.
+        let executor = options.exec.as_ref().map(|template| exec::Executor::new(template, options));
+        let run_each = options.exec.as_ref().is_some_and(|template| !template.is_batch());
+        Results {
+            hits: vec![],
+            streaming: !options.sort || run_each,
+            executor,
+            options,
+        }
+    }
+
+    fn output(&mut self, hit: Hit) {
+        match self.executor {
+            Some(ref mut executor) => executor.add(&hit.path),
+            None => display::print_as_path(hit.path.as_path(), hit.info.as_ref(), &hit.matched.positions, self.options),
+        }
+    }
+
+    fn add(&mut self, hit: Hit) {
+        if self.streaming {
+            self.output(hit);
+            return;
+        }
+        self.hits.push(hit);
+        if self.hits.len() >= MAX_RANKED_RESULTS {
+            v(format!("More than {} results, no longer ranking results.", MAX_RANKED_RESULTS), self.options);
+            self.flush();
+            self.streaming = true;
+        }
+    }
+
+    fn flush(&mut self) {
+        // Best scores first, ties are broken by path so output is deterministic.
+        self.hits.sort_by(|a, b| b.matched.score.cmp(&a.matched.score).then_with(|| a.path.cmp(&b.path)));
+        let hits: Vec<Hit> = self.hits.drain(..).collect();
+        for hit in hits {
+            self.output(hit);
+        }
+    }
+
+    fn finish(mut self) -> bool {
+        //! Outputs any results still waiting to be ranked, and returns false if any commands failed.
+        self.flush();
+        match self.executor {
+            Some(executor) => executor.finish(),
+            None => true,
+        }
+    }
+}
+
 pub fn list (path: &str, options: &super::Options) {
     //! Just iterates through the current directory.
     let current_path = path::PathBuf::from(path);
     let current_path_str = current_path.to_str().unwrap_or("");
 
     if !current_path.is_dir() {
-        display::print_as_filename(current_path.as_path(), options);
+        display::print_start(options);
+        if let Some(info) = hit_info(&current_path, None, options) {
+            display::print_as_filename(current_path.as_path(), info.as_ref(), options);
+        }
+        display::print_end(options);
+        stats::print(options);
         return;
     }
 
@@ -41,175 +215,734 @@ pub fn list (path: &str, options: &super::Options) {
             return;
         }
     };
+    stats::add(&stats::DIRECTORIES, 1);
 
-    for dir_entry in dir_entries {
-        if let Ok(current_pathbuf) = dir_entry  {
-            display::print_as_filename(current_pathbuf.path().as_path(), options);
+    display::print_start(options);
+    for dir_entry in dir_entries.flatten() {
+        stats::add(&stats::ENTRIES, 1);
+        if !options.show_hidden && is_hidden(&dir_entry.file_name()) {
+            continue;
+        }
+        let path = dir_entry.path();
+        if let Some(info) = hit_info(&path, Some(&dir_entry), options) {
+            display::print_as_filename(path.as_path(), info.as_ref(), options);
         }
     }
+    display::print_end(options);
+    stats::print(options);
 }
 
-pub fn find (pattern: &str, options: &super::Options) {
-    v(format!("Looking for: {}, insensitive: {}", pattern, options.insensitive), options);
+pub fn find (pattern: &str, options: &super::Options) -> bool {
+    //! Searches for `pattern` and prints or runs commands for what's found. Returns false if any
+    //! of those commands failed.
+    let insensitive = is_insensitive(pattern, options);
+    v(format!("Looking for: {}, insensitive: {}", pattern, insensitive), options);
 
-    if pattern.len() == 0 {
-        v(format!("No valid input given."), options);
-        return;
+    // An empty pattern matches every path, which only makes sense with an expression to filter them.
+    if pattern.is_empty() && options.expression.is_none() {
+        v("No valid input given.".to_string(), options);
+        return true;
     }
 
     // Set up search context for searching: the search string and regular expression if needed.
-    let s = make_case_insensitive(pattern, options);
-    let found_regex;
-    let mut search_regex = None;
-    match options.search_type {
-        super::SearchType::Regex => {
-            found_regex = match regex::Regex::new(pattern) {
-                Ok(r) => r,
-                Err(e) => {
-                    v(format!("Failed to parse regular expression: {}", e), options);
-                    return;
-                }
-            };
-            search_regex = Some(&found_regex);
-        },
-        _ => (),
+    let search_regex = match build_regex(pattern, options) {
+        Ok(r) => r,
+        Err(e) => {
+            v(format!("Failed to parse regular expression: {}", e), options);
+            return true;
+        }
+    };
+    // Cached directory listings don't say where symbolic links point, or what isn't ignored without
+    // ignore files.
+    let walk_cache = if options.cache && !options.follow && options.default_ignores() { cache::WalkCache::load(options) } else { None };
+    let search = SearchContext {
+        search: make_case_insensitive(pattern, insensitive),
+        insensitive,
+        regex: search_regex.as_ref(),
+        include_ignored: false,
+        cache: walk_cache.as_ref(),
+        options,
+    };
+
+    // Directories are searched on `options.threads` threads which send what they find to a single
+    // thread for printing, so output isn't interleaved and can be ranked.
+    let (sender, receiver) = mpsc::channel();
+    let (success, walked) = thread::scope(|scope| {
+        let printer = scope.spawn(move || {
+            let mut results = Results::new(options);
+            display::print_start(options);
+            for hit in receiver {
+                results.add(hit);
+            }
+            let success = results.finish();
+            display::print_end(options);
+            success
+        });
+        // The printing thread only goes away once searching is done, so sending can't fail.
+        let sink = |hit| sender.send(hit).is_ok();
+        let walked = !search_daemon(pattern, options, &sink) && !search_index(&search, &sink);
+        if walked {
+            walk(&search, &sink);
+        }
+        drop(sender);
+        (printer.join().unwrap_or(false), walked)
+    });
+    // A walk that skipped directories would make the cache forget them.
+    if let Some(c) = walk_cache.filter(|_| walked && !options.limits_walk()) {
+        c.save(options);
+    }
+    stats::print(options);
+    success
+}
+
+fn build_regex(pattern: &str, options: &super::Options) -> Result<Option<bytes::Regex>, ::regex::Error> {
+    if let super::SearchType::Regex = options.search_type {
+        // Regular expressions match the bytes of paths, so they can match paths that aren't UTF-8.
+        return bytes::RegexBuilder::new(pattern)
+            .case_insensitive(is_insensitive(pattern, options))
+            .build()
+            .map(Some);
+    }
+    Ok(None)
+}
+
+pub fn match_all<P, I>(pattern: &str, options: &super::Options, paths: I) -> Result<Vec<(P, Match)>, String>
+    where P: AsRef<path::Path>, I: Iterator<Item = P> {
+    //! Matches `paths` against `pattern` as a search would and returns the matches best first, for
+    //! the daemon. Paths that don't match are dropped as they go.
+    let search_regex = build_regex(pattern, options).map_err(|e| e.to_string())?;
+    let insensitive = is_insensitive(pattern, options);
+    let search = SearchContext {
+        search: make_case_insensitive(pattern, insensitive),
+        insensitive,
+        regex: search_regex.as_ref(),
+        include_ignored: false,
+        cache: None,
+        options,
+    };
+    let mut matches: Vec<(P, Match)> = paths
+        .filter_map(|path| {
+            let matched = match_path(&search, path.as_ref(), &Name::from_path(path.as_ref()));
+            matched.map(|m| (path, m))
+        })
+        .collect();
+    matches.sort_by(|a, b| b.1.score.cmp(&a.1.score).then_with(|| a.0.as_ref().cmp(b.0.as_ref())));
+    Ok(matches)
+}
+
+// A pattern that's matched against paths like a search pattern is, for `--where`.
+#[derive(Debug)]
+pub struct Matcher {
+    search: String,
+    insensitive: bool,
+    regex: Option<bytes::Regex>,
+    options: super::Options,
+}
+
+impl Matcher {
+
+    pub fn new(pattern: &str, search_type: super::SearchType, names_only: bool, options: &super::Options) -> Result<Matcher, String> {
+        let options = super::Options {
+            search_type,
+            search_names_only: names_only,
+            expression: None,
+            ..options.clone()
+        };
+        let regex = build_regex(pattern, &options).map_err(|e| e.to_string())?;
+        let insensitive = is_insensitive(pattern, &options);
+        Ok(Matcher {
+            search: make_case_insensitive(pattern, insensitive),
+            insensitive,
+            regex,
+            options,
+        })
+    }
+
+    pub fn is_match(&self, path: &path::Path) -> bool {
+        let search = SearchContext {
+            search: self.search.clone(),
+            insensitive: self.insensitive,
+            regex: self.regex.as_ref(),
+            include_ignored: false,
+            cache: None,
+            options: &self.options,
+        };
+        match_path(&search, path, &Name::from_path(path)).is_some()
+    }
+}
+
+// A path, whether it's a directory and whether it's ignored.
+#[cfg(target_os = "linux")]
+pub type ScannedEntry = (path::PathBuf, bool, bool);
+
+#[cfg(target_os = "linux")]
+pub fn scan_dir(dir: &path::Path, rule_set: &Arc<ignore::RuleSet>, options: &super::Options)
+                -> Option<(Arc<ignore::RuleSet>, Vec<ScannedEntry>)> {
+    //! Lists `dir` for the daemon, which walks directories itself to keep track of them. Returns
+    //! each entry's path, whether it's a directory and whether it's ignored, along with the rules
+    //! for its subdirectories.
+    let entries = read_dir(dir, options)?;
+    let rule_set = match extend_rules(rule_set, dir, &entries, options) {
+        Some(extended) => Arc::new(extended),
+        None => rule_set.clone(),
+    };
+    let entries = entries.into_iter()
+        .map(|e| {
+            let ignored = rule_set.is_excluded(&e.path, e.is_dir, options);
+            (e.path, e.is_dir, ignored)
+        })
+        .collect();
+    Some((rule_set, entries))
+}
+
+fn search_daemon(pattern: &str, options: &super::Options, sink: &(dyn Fn(Hit) -> bool + Sync)) -> bool {
+    //! Asks a running `fe daemon` of the search directory for matches instead of walking
+    //! directories. Returns false if there's no daemon to ask.
+    // The daemon searches every directory.
+    if !options.use_daemon || !options.walks_every_path() {
+        return false;
+    }
+    let matches = match daemon::query(pattern, options) {
+        Some(m) => m,
+        None => return false,
+    };
+    // The daemon knows about hidden paths, they're left out here.
+    let search_dir = path::Path::new(&options.search_dir);
+    for (path, matched) in matches {
+        if !options.show_hidden && in_hidden_dir(path.strip_prefix(search_dir).unwrap_or(&path)) {
+            continue;
+        }
+        let info = match hit_info(&path, None, options) {
+            Some(info) => info,
+            None => continue,
+        };
+        let hit = Hit {
+            path,
+            info,
+            matched,
+            ignored: false,
+        };
+        if !sink(hit) {
+            break;
+        }
+    }
+    true
+}
+
+pub fn walk_paths(options: &super::Options, sender: mpsc::Sender<path::PathBuf>) {
+    //! Sends every path a search would look at, ignoring ones that are ignored, to `sender`. The
+    //! walk stops early if the receiver is dropped.
+    let search = SearchContext {
+        search: String::new(),
+        insensitive: false,
+        regex: None,
+        include_ignored: false,
+        cache: None,
+        options,
+    };
+    walk(&search, &|hit| sender.send(hit.path).is_ok());
+}
+
+pub fn walk_entries(options: &super::Options, sender: mpsc::Sender<(path::PathBuf, bool)>) {
+    //! Like `walk_paths` but also sends ignored paths, with whether they're ignored. Ignored
+    //! directories aren't walked. Every path is sent, `--type` and other filters only apply to
+    //! searches.
+    let options = &super::Options {
+        types: vec![],
+        filters: filter::Filters::default(),
+        expression: None,
+        max_depth: None,
+        min_depth: 0,
+        prune: vec![],
+        follow: false,
+        one_file_system: false,
+        fs_types: vec![],
+        exclude_fs_types: vec![],
+        show_hidden: true,
+        overrides: vec![],
+        ..options.clone()
     };
     let search = SearchContext {
-        search: &s[..],
-        regex: search_regex,
-        options: options,
+        search: String::new(),
+        insensitive: false,
+        regex: None,
+        include_ignored: true,
+        cache: None,
+        options,
+    };
+    walk(&search, &|hit| sender.send((hit.path, hit.ignored)).is_ok());
+}
+
+fn search_index(search: &SearchContext, sink: &(dyn Fn(Hit) -> bool + Sync)) -> bool {
+    //! Searches the index built by `fe index build` instead of walking directories, if there is one
+    //! and it's up to date. Returns false if the directories need to be walked instead.
+    let options = search.options;
+    if !options.use_index || !options.walks_every_path() {
+        return false;
+    }
+    let index = match index::Index::load(options) {
+        Some(i) => i,
+        None => return false,
     };
+    if !index.is_fresh(options) {
+        v(String::from("The index is out of date, searching directories instead."), options);
+        return false;
+    }
+
+    // The index is split between threads like directories are when walking.
+    let root = path::Path::new(&options.search_dir);
+    let stopped = AtomicBool::new(false);
+    let chunk_size = index.entries.len() / options.threads.max(1) + 1;
+    thread::scope(|scope| {
+        for entries in index.entries.chunks(chunk_size) {
+            let stopped = &stopped;
+            scope.spawn(move || {
+                let shown = |e: &&index::Entry| !e.ignored && (options.show_hidden || !in_hidden_dir(e.path()));
+                for entry in entries.iter().filter(shown) {
+                    if stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    stats::add(&stats::INDEXED, 1);
+                    let path = root.join(entry.path());
+                    if let Some(matched) = match_path(search, &path, &Name::from_path(&path)) {
+                        let info = match hit_info(&path, None, options) {
+                            Some(info) => info,
+                            None => continue,
+                        };
+                        let hit = Hit {
+                            path,
+                            info,
+                            matched,
+                            ignored: false,
+                        };
+                        if !sink(hit) {
+                            stopped.store(true, Ordering::Relaxed);
+                        }
+                    }
+                }
+            });
+        }
+    });
+    true
+}
+
+fn walk(search: &SearchContext, sink: &(dyn Fn(Hit) -> bool + Sync)) {
+    //! Walks `options.search_dir` giving `sink` every path that matches `search`. The walk stops
+    //! early if `sink` returns false.
+    let options = search.options;
 
     // Set up state for searching: the ignore rules and directory queue. Rules are stored in a vector
     // and reference them by rule_index so it doesn't have to store references to rules in subsequent
     // directories found. The rule index is associated with a directory and attached to the directory search queue.
     // This is done because .gitignore rules are merged in root and subsequent ignore files found later in
-    // subdirectories.
-    let mut rule_sets = vec![ignore::RuleSet::new_default()];
-    let dir = Dir {
-        path: path::PathBuf::from(&options.search_dir),
+    // subdirectories. The vector is only ever appended to so indices stay valid while other threads add to it.
+    let root = path::PathBuf::from(&options.search_dir);
+    let rule_sets = RwLock::new(vec![Arc::new(ignore::RuleSet::new_root(&root, options))]);
+    let file_systems = mounts::FileSystems::new(&root, options);
+    let walker = walk::Walker::new(options.threads);
+    walker.push(0, Dir {
+        path: root,
+        depth: 0,
+        ancestors: vec![],
         rule_index: 0,
-    };
-    let mut dirs = vec![dir];
+        rules_changed: search.cache.is_none_or(|c| c.sources_changed),
+    });
+    let sink = |hit| if !sink(hit) { walker.stop() };
+    walker.run(|worker, dir| search_dir(search, &walker, worker, &rule_sets, &file_systems, dir, &sink));
+}
 
-    'search_loop: loop {
-        // Get next entry or finish.
-        let current_path = match dirs.pop() {
-            Some(p) => { p },
-            None => return,
+fn search_dir(search: &SearchContext, walker: &walk::Walker<Dir>, worker: usize, rule_sets: &RwLock<Vec<Arc<ignore::RuleSet>>>,
+              file_systems: &mounts::FileSystems, current_path: Dir, sink: &(dyn Fn(Hit) + Sync)) {
+    let mut ancestors = vec![];
+    if search.options.follow || file_systems.is_active() {
+        stats::add(&stats::STAT_CALLS, 1);
+        let id = match fs::metadata(&current_path.path) {
+            Ok(m) => (m.dev(), m.ino()),
+            Err(e) => {
+                v(format!("Failed to get metadata for {:?}: {}", current_path.path, e), search.options);
+                return;
+            },
         };
-        let current_path_str = match current_path.path.to_str() {
-            Some(s) => s,
-            None => continue,
+        if !file_systems.allows(&current_path.path, id.0, search.options) {
+            return;
+        }
+        if search.options.follow {
+            if current_path.ancestors.contains(&id) {
+                v(format!("Not searching {:?}, it's a symbolic link back to a directory it's in.", current_path.path), search.options);
+                return;
+            }
+            ancestors = current_path.ancestors.clone();
+            ancestors.push(id);
+        }
+    }
+
+    // With a walk cache directories that haven't changed since the last search aren't read again.
+    let stamp = search.cache.map(|_| cache::dir_stamp(&current_path.path));
+    let cached = search.cache.and_then(|c| c.get(&current_path.path));
+    let unchanged = cached.filter(|c| Some(c.stamp) == stamp);
+    let entries: Vec<Entry> = match unchanged {
+        Some(c) => {
+            stats::add(&stats::CACHED_DIRECTORIES, 1);
+            c.entries.iter()
+                .map(|e| Entry {
+                    path: current_path.path.join(&e.name),
+                    is_dir: e.is_dir,
+                    dir_entry: None,
+                })
+                .collect()
+        },
+        None => match read_dir(&current_path.path, search.options) {
+            Some(entries) => entries,
+            None => return,
+        },
+    };
+
+    // Check if there's an ignore for the current directory.
+    let mut rule_index = current_path.rule_index;
+    let mut rule_set = rule_sets.read().unwrap()[rule_index].clone();
+    if let Some(extended) = extend_rules(&rule_set, &current_path.path, &entries, search.options) {
+        rule_set = Arc::new(extended);
+        let mut rule_sets = rule_sets.write().unwrap();
+        rule_sets.push(rule_set.clone());
+        rule_index = rule_sets.len() - 1;
+    }
+
+    // What was ignored last time still is, unless an ignore file for this directory or one of its
+    // parents changed. Editing one changes the newest of their stamps, adding or removing one
+    // changes the directory.
+    let ignore_file = if search.cache.is_some() {
+        entries.iter()
+            .filter(|e| is_ignore_file(e, search.options))
+            .map(|e| index::stamp(&e.path))
+            .max()
+            .flatten()
+    } else {
+        None
+    };
+    let had_ignore_file = cached.is_some_and(|c| c.entries.iter().any(|e| ignore::is_ignore_file(&e.name, search.options)));
+    let rules_changed = current_path.rules_changed || cached.is_none_or(|c| c.ignore_file != ignore_file)
+        || (unchanged.is_none() && had_ignore_file);
+    let cached_ignored = unchanged.filter(|_| !rules_changed).map(|c| &c.entries);
+
+    // Iterate through directory entries.
+    let mut listed = vec![];
+    for (i, entry) in entries.into_iter().enumerate() {
+        let ignored = match cached_ignored {
+            Some(cached_entries) => cached_entries[i].ignored,
+            None => rule_set.is_excluded(&entry.path, entry.is_dir, search.options),
         };
+        if search.cache.is_some() {
+            listed.push(cache::CachedEntry {
+                name: entry.path.file_name().unwrap_or_default().to_os_string(),
+                is_dir: entry.is_dir,
+                ignored,
+            });
+        }
+        if let Some(path) = search_dir_entry(search, entry, current_path.depth + 1, ignored, sink) {
+            walker.push(worker, Dir {
+                path,
+                depth: current_path.depth + 1,
+                ancestors: ancestors.clone(),
+                rule_index,
+                rules_changed,
+            });
+        }
+    }
+
+    if let Some(c) = search.cache {
+        c.add(&current_path.path, cache::CachedDir {
+            stamp: stamp.unwrap_or(None),
+            ignore_file,
+            entries: listed,
+        });
+    }
+}
+
+fn is_ignore_file(entry: &Entry, options: &super::Options) -> bool {
+    entry.path.file_name().is_some_and(|name| ignore::is_ignore_file(name, options))
+}
 
-        // Check if there's an ignore for the current directory.
-        let mut rule_index = current_path.rule_index;
-        let ignore_path_str = &format!("{}/.gitignore", current_path_str);
-        let ignore_path = path::Path::new(ignore_path_str);
-        match ignore::RuleSet::extend(&rule_sets[rule_index], &ignore_path, search.options) {
-            Ok(rule_set) => {
-                v(format!("Found a .gitignore: {}", current_path_str), search.options);
-                rule_sets.push(rule_set);
-                rule_index = rule_sets.len() - 1;
+fn extend_rules(rule_set: &ignore::RuleSet, dir: &path::Path, entries: &[Entry], options: &super::Options) -> Option<ignore::RuleSet> {
+    //! Returns `rule_set` with the rules of the ignore files in `dir` added, if it has any. They're
+    //! added in the order `ignore::ignore_file_names` gives, so later ones win.
+    if !entries.iter().any(|e| is_ignore_file(e, options)) {
+        return None;
+    }
+    let mut extended: Option<ignore::RuleSet> = None;
+    for name in ignore::ignore_file_names(options) {
+        let ignore_path = dir.join(name);
+        if !entries.iter().any(|e| e.path == ignore_path) {
+            continue;
+        }
+        match ignore::RuleSet::extend(extended.as_ref().unwrap_or(rule_set), &ignore_path, options) {
+            Ok(e) => {
+                v(format!("Found a {}: {}", name, dir.to_string_lossy()), options);
+                extended = Some(e);
             },
-            _ => (),
-        };
+            Err(ignore::IgnoreError::Io(e)) => v(format!("Failed to read {:?}: {}", ignore_path, e), options),
+        }
+    }
+    extended
+}
+
+fn read_dir(dir: &path::Path, options: &super::Options) -> Option<Vec<Entry>> {
+    let dir_entries = match dir.read_dir() {
+        Ok(e) => e,
+        Err(e) => {
+            v(format!("Failed to read directory entries for {} because {}", dir.to_string_lossy(), e), options);
+            return None;
+        }
+    };
+    stats::add(&stats::DIRECTORIES, 1);
 
-        let dir_entries = match current_path.path.read_dir() {
-            Ok(e) => e,
+    let mut entries = vec![];
+    for dir_entry in dir_entries.flatten() {
+        // The file type usually comes from the directory listing itself, so this avoids a stat.
+        // Only symbolic links that are followed have to be stat'ed to find out what they link to.
+        let is_dir = match dir_entry.file_type() {
+            Ok(t) if t.is_symlink() && options.follow => {
+                stats::add(&stats::STAT_CALLS, 1);
+                dir_entry.path().metadata().is_ok_and(|m| m.is_dir())
+            },
+            Ok(t) => {
+                stats::add(&stats::STAT_CALLS_SAVED, 1);
+                t.is_dir()
+            },
             Err(e) => {
-                v(format!("Failed to read directory entries for {} because {}", current_path_str, e), search.options);
-                continue 'search_loop;
-            }
+                v(format!("Failed to get the file type of {:?}: {}", dir_entry.path(), e), options);
+                continue;
+            },
         };
+        entries.push(Entry {
+            path: dir_entry.path(),
+            is_dir,
+            dir_entry: Some(dir_entry),
+        });
+    }
+    Some(entries)
+}
 
-        // Iterate through directory entries.
-        for dir_entry in dir_entries {
-            match search_dir_entry(&search, dir_entry, &mut rule_sets[rule_index]) {
-                Some(path) => {
-                    dirs.push(Dir {
-                        path: path.path(),
-                        rule_index: rule_index,
-                    });
+fn search_dir_entry(search: &SearchContext, entry: Entry, depth: usize, ignored: bool, sink: &(dyn Fn(Hit) + Sync)) -> Option<path::PathBuf> {
+    //! Gives `sink` the entry if it matches, and returns its path if it's a directory to walk.
+    //! `depth` is how many directories deep the entry is.
+    stats::add(&stats::ENTRIES, 1);
+    let path = entry.path;
+    if !search.options.show_hidden && is_hidden(path.file_name().unwrap_or_default()) {
+        return None;
+    }
+    if ignored {
+        if search.include_ignored {
+            sink(Hit {
+                path,
+                info: None,
+                matched: Match {
+                    score: 0,
+                    positions: vec![],
                 },
-                None => continue,
-            }
+                ignored: true,
+            });
         }
+        return None;
     }
+
+    let options = search.options;
+    let pruned = entry.is_dir && is_pruned(&path, options);
+    let deep_enough = depth >= options.min_depth && options.max_depth.is_none_or(|max| depth <= max);
+    let printed = deep_enough && (!pruned || options.print_pruned);
+    if let Some(matched) = match_path(search, &path, &Name::from_path(&path)).filter(|_| printed) {
+        if let Some(info) = hit_info(&path, entry.dir_entry.as_ref(), options) {
+            sink(Hit {
+                path: path.clone(),
+                info,
+                matched,
+                ignored: false,
+            });
+        }
+    }
+
+    // If we're looking at a directory return it to be iterated through.
+    if entry.is_dir && !pruned && options.max_depth.is_none_or(|max| depth < max) {
+        return Some(path);
+    }
+    None
 }
 
-fn search_dir_entry(search: &SearchContext, dir_entry: Result<fs::DirEntry, io::Error>, rule_set: &mut ignore::RuleSet) -> Option<fs::DirEntry> {
-    let dir_entry: fs::DirEntry = match dir_entry {
-        Ok(entity) => entity,
-        _ => return None,
-    };
+pub fn is_hidden(name: &OsStr) -> bool {
+    name.as_bytes().first() == Some(&b'.')
+}
 
-    // Get and finesse entry path.
-    let path = dir_entry.path();
-    let path_str = match path.to_str() {
-        Some(mut s) => {
-            if s.starts_with("./") {
-                s = &s[2..];
-            }
-            s
-        },
-        _ => {
-            v(format!("Found invalid path string."), search.options);
-            return None;
-        },
-    };
+fn in_hidden_dir(relative: &path::Path) -> bool {
+    //! Whether `relative`, a path in the search directory, or a directory it's in is hidden.
+    relative.components().any(|c| match c {
+        path::Component::Normal(name) => is_hidden(name),
+        _ => false,
+    })
+}
 
-    let is_dir = path.is_dir();
-    if let Some(filename) = path.file_name().map(|n| n.to_str()) {
-        if rule_set.is_excluded(filename.unwrap(), is_dir, search.options) {
-            return None;
+pub fn is_pruned(dir: &path::Path, options: &super::Options) -> bool {
+    //! Whether `--prune` matches the name of `dir` or its path in the search directory.
+    let relative = dir.strip_prefix(&options.search_dir).unwrap_or(dir);
+    let name = dir.file_name().unwrap_or_default().to_string_lossy();
+    options.prune.iter().any(|p| p.matches(&name) || p.matches_path(relative))
+}
+
+// A path that matched, being checked against `--type`, the other filters and `--where`. Its file
+// info is fetched the first time one of them needs it.
+pub struct Candidate<'a> {
+    pub path: &'a path::Path,
+    dir_entry: Option<&'a fs::DirEntry>,
+    // `None` until it's fetched, then `Some(None)` if it couldn't be.
+    info: Option<Option<FileInfo>>,
+    pub options: &'a super::Options,
+}
+
+impl<'a> Candidate<'a> {
+
+    pub fn new(path: &'a path::Path, options: &'a super::Options) -> Candidate<'a> {
+        Candidate {
+            path,
+            dir_entry: None,
+            info: None,
+            options,
         }
-    } else {
-        v(format!("Not matching against {} as it has no filename", path_str), search.options);
     }
 
-    let mut s = path_str;
-    if search.options.search_names_only {
-        s = match path.file_name().and_then(|n| n.to_str()) {
-            Some(n) => n,
-            None => {
-                v(format!("No file name found for {}", path_str), search.options);
-                return None;
-            }
+    pub fn info(&mut self) -> Option<&FileInfo> {
+        if self.info.is_none() {
+            let info = match self.dir_entry {
+                Some(dir_entry) => FileInfo::from_entry(dir_entry),
+                None => FileInfo::new(self.path),
+            };
+            self.info = Some(match info.or_else(|_| FileInfo::without_following(self.path)) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    v(format!("Failed to get the file info of {:?}: {}", self.path, e), self.options);
+                    None
+                },
+            });
         }
+        self.info.as_ref().and_then(|info| info.as_ref())
     }
+}
 
-    let s = make_case_insensitive(s, search.options);
-    let found = match search.options.search_type {
-        super::SearchType::Regex => regex_path_match_search(&s[..], search),
-        super::SearchType::Exact => &s[..] == search.search,
-        super::SearchType::Fuzzy => fuzzy_path_match_search(&s[..], search),
+fn hit_info(path: &path::Path, dir_entry: Option<&fs::DirEntry>, options: &super::Options) -> Option<Option<FileInfo>> {
+    //! Fetches the file info of a path that matched, if printing it, `--type`, the other filters
+    //! or `--where` need it. Returns `None` if the path is filtered out.
+    let filtered = !options.types.is_empty() || !options.filters.is_empty();
+    let mut candidate = Candidate {
+        path,
+        dir_entry,
+        info: None,
+        options,
     };
-    if found {
-        display::print_as_path(path.as_path(), search.options);
+    if filtered && !candidate.info().is_some_and(|info| info.is_any_of(path, &options.types) && options.filters.matches(info)) {
+        return None;
+    }
+    if options.expression.as_ref().is_some_and(|expression| !expression.matches(&mut candidate)) {
+        return None;
+    }
+    match candidate.info {
+        Some(info) => Some(info),
+        None => Some(match dir_entry {
+            Some(dir_entry) if options.needs_file_info() => FileInfo::from_entry(dir_entry).ok(),
+            _ => None,
+        }),
+    }
+}
+
+fn match_path(search: &SearchContext, path: &path::Path, path_name: &Name) -> Option<Match> {
+    if search.search.is_empty() && search.regex.is_none() {
+        return Some(Match {
+            score: 0,
+            positions: vec![],
+        });
     }
 
-    // If we're looking at a directory return it to be iterated through.
-    if is_dir {
-        return Some(dir_entry);
+    let file_name;
+    let mut searched = path_name;
+    if search.options.search_names_only {
+        file_name = Name::from_file_name(path);
+        searched = &file_name;
     }
-    None
+
+    // Matched positions are reported relative to the printed path, which may be longer than the file name searched.
+    let offset = path_name.text.chars().count() - searched.text.chars().count();
+    let original = &searched.text;
+    let s = make_case_insensitive(original, search.insensitive);
+    let matched = match search.options.search_type {
+        super::SearchType::Regex => regex_path_match_search(searched, search),
+        super::SearchType::Exact => exact_path_match_search(&s[..], search),
+        super::SearchType::Fuzzy => fuzzy_path_match_search(&s[..], original, search),
+    };
+    matched.map(|mut matched| {
+        for position in &mut matched.positions {
+            *position += offset;
+        }
+        matched
+    })
 }
 
-fn regex_path_match_search(path_str: &str, search: &SearchContext) -> bool {
-    let search_regex = search.regex;
-    let r = search_regex.expect("Missing a regular expression!").is_match(path_str);
-    vv(format!("Regexp matching {} against {}: {}", path_str, search.search, r), search.options);
-    r
+fn exact_path_match_search(path_str: &str, search: &SearchContext) -> Option<Match> {
+    if path_str != search.search {
+        return None;
+    }
+    Some(Match {
+        score: 0,
+        positions: (0..path_str.chars().count()).collect(),
+    })
+}
+
+fn regex_path_match_search(path_name: &Name, search: &SearchContext) -> Option<Match> {
+    //! Matches reports the spans of the regular expression's capture groups, or the span of the
+    //! whole match if it has no capture groups.
+    let search_regex = search.regex.expect("Missing a regular expression!");
+    let captures = search_regex.captures(&path_name.bytes);
+    vv(format!("Regexp matching {} against {}: {}", path_name.escaped(), search.search, captures.is_some()), search.options);
+    let captures = captures?;
+
+    let mut spans: Vec<(usize, usize)> = captures.iter().skip(1).flatten().map(|m| (m.start(), m.end())).collect();
+    if spans.is_empty() {
+        spans = captures.get(0).map(|m| (m.start(), m.end())).into_iter().collect();
+    }
+    Some(Match {
+        score: 0,
+        positions: path_name.char_indices_of_bytes(&spans),
+    })
+}
+
+fn word_starts(path: &[char], boundaries: &super::WordBoundaries) -> Vec<bool> {
+    //! Returns which characters of `path` begin a word. Words always begin at the start of the path
+    //! and after a non-alphanumeric character like `/`, `.`, `_` or `-`. With `WordBoundaries::CamelCase`
+    //! words also begin at a lower to upper case change, at the last upper case letter of an acronym
+    //! followed by a lower case letter, and where letters change to digits or digits to letters, so
+    //! `PatchSyntaxException`, `HTTPServer` and `utf8decode` are three words each.
+    (0..path.len())
+        .map(|j| {
+            let c = path[j];
+            if j == 0 || !path[j - 1].is_alphanumeric() {
+                return true;
+            }
+            if let super::WordBoundaries::Separators = *boundaries {
+                return false;
+            }
+            let previous = path[j - 1];
+            let next = path.get(j + 1).cloned().unwrap_or(' ');
+            (previous.is_lowercase() && c.is_uppercase())
+                || (previous.is_uppercase() && c.is_uppercase() && next.is_lowercase())
+                || (previous.is_numeric() != c.is_numeric())
+        })
+        .collect()
 }
 
-fn fuzzy_path_match_search(path_str: &str, search: &SearchContext) -> bool {
+fn fuzzy_path_match_search(path_str: &str, original: &str, search: &SearchContext) -> Option<Match> {
     //!
     //! `fuzzy_path_match_search` attempts to make a fuzzy match based on the following rules:
     //!
     //!     * Words are roughly consecutive UTF8 alphanumeric characters in a path.
-    //!     * Words begin at the start of the path or at the first non-alphanumeric character.
+    //!     * Words begin at the start of the path or at the first non-alphanumeric character. Unless
+    //!         `options.word_boundaries` is `Separators` they also begin at camelCase humps and
+    //!         where letters and digits meet, see `word_starts`. `original` is `path_str` before it was
+    //!         made case insensitive, and is what word starts are found in.
     //!     * Non-alphanumeric characters begin words because non-alphanumeric matches must also work.
     //!     * Matches can only begin and continue on the first character of a word.
     //!     * Character by character matching continues in a word until it fails.
@@ -227,100 +960,169 @@ fn fuzzy_path_match_search(path_str: &str, search: &SearchContext) -> bool {
     //!
     //!     `src/shared/foo.js` matches because the word `shared` matches the entire search string.
     //!
+    //! Example matches for `PSE`:
+    //!
+    //!     `src/PatchSyntaxException.java` matches because `P`, `S` and `E` each start a word.
+    //!
     //! Example matches for `foo.js`:
     //!
     //!     `src/foo/bar.js` matches because `foo` matches the `foo` and `.js` matches the `.js` in the path.
     //!
     //!     `src/bar/foo.json` matches because the first six characters of `foo.json` match `foo.js`.
     //!
-    let input = search.search;
+    //! A path usually matches in more than one way, so instead of a yes or no answer the best scoring
+    //! way to match is found and its score and matched characters returned, or `None` if the path
+    //! doesn't match at all. The score rewards:
+    //!
+    //!     * Every matched character.
+    //!     * Matches at the start of a word.
+    //!     * Runs of consecutive matched characters.
+    //!     * Matches in the file name rather than in its parent directories.
+    //!
+    //! and is reduced by one for every character in the path so shorter paths win ties. For `rs`,
+    //! `src/main.rs` therefore scores well above `.git/hooks/pre-rebase.sample`.
+    //!
     let options = search.options;
-    if path_str.len() == 0 {
-        return false;
+    if path_str.is_empty() {
+        return None;
     }
-    vv(format!("Matching {} against {}", path_str, input), options);
-
-    // ** Set pre-loop path search state.
-
-    // The `input_chars` variable is the character iterator for the search input. It is reset whenever
-    // the end of `path_chars` is reached while `match_in_progress` is true. If `input_chars`
-    // iterates to its end the path matches the input and `fuzzy_path_match_search` returns true.
-    let mut input_chars = input.chars();
-    // The `path_chars` variable is the character iterator for the path. It is reset to the index
-    // stored in `index_matched_at` if path_chars runs out while `match_in_progress` is true. Otherwise
-    // it is assumed this patch does not match the search input and `fuzzy_path_match_search` returns false.
-    let mut path_chars = path_str.chars();
-    // `input` is guaranteed to be greater than 0 chars long so unwrap is safe here.
-    let mut current_input_char = input_chars.next().unwrap();
-
-    // Words are consecutive UTF8 alphanumeric characters in a path. `matching_current_word` is how
-    // character matches are tracked one after another inside a word starting from its first
-    // character. Once character is found that doesn't match, this is set to false. Matching stops.
-    // It start again at the start of the next word in the path. Non-alphanumeric characters
-    // are still checked for matches however.
-    let mut matching_current_word = true;
-    // The `match_in_progress` variable tracks that a character starting at the
-    // beginning of a word waws matched, beginning the process of a fuzzy match. This variable
-    // is reset to false when it runs out of path characters to check.
-    let mut match_in_progress = false;
-    // The `index_matched_at` variable tracks the first path character that matched the input
-    // whether that was an alphanumeric character at the beginning of a word or a non-alphanumeric
-    // character in between words.
-    let mut index_matched_at = 0;
-
-    // The loop that iterates character by character through input and path, rewinding and retreating,
-    // as necessary. `input_chars` is sometimes rewound all the way to its beginning, `path_chars` is
-    // only ever reset to the character *after* where the last match first started.
-    'pathsearch: loop {
-        let current_path_char;
-        let next_possible_path_char = path_chars.next();
-
-        if next_possible_path_char.is_some() {
-            if !match_in_progress {
-                // This is the next character's index, not the current one, it may not exist! We catch that
-                // condition in the else if below.
-                index_matched_at += 1;
-            }
-            current_path_char = next_possible_path_char.unwrap();
-        } else if index_matched_at == path_str.len() {
-            // There was a match in progress, but it started at the last possible character. Matching is over.
-            return false;
-        } else {
-            if !match_in_progress {
-                // The loop is finished as it is has run out of characters and is not rewound as
-                // no match is in progress.
-                return false;
-            }
-            match_in_progress = false;
-            path_chars = path_str.chars();
-            path_chars.nth(index_matched_at).unwrap(); // Safe due to else if above.
-            input_chars = input.chars();
-            current_input_char = input_chars.next().unwrap();
-            vv(format!("Resetting search {} against {}, index {}", path_str, input, index_matched_at), search.options);
-            continue 'pathsearch;
-        }
+    vv(format!("Matching {} against {}", path_str, search.search), options);
 
-        let is_alphanumeric = current_path_char.is_alphanumeric();
-        if !is_alphanumeric {
-            // Potentially starting a new word.
-            matching_current_word = true;
-        }
+    let input: Vec<char> = search.search.chars().collect();
+    let path: Vec<char> = path_str.chars().collect();
 
-        if !matching_current_word {
-            // Current word was not matched, proceed until a non-alphanumeric character is found.
-            continue;
+    // Most paths don't contain the input as a subsequence at all, rule those out cheaply before
+    // scoring.
+    let mut input_chars = input.iter().peekable();
+    for c in &path {
+        if input_chars.peek() == Some(&c) {
+            input_chars.next();
         }
+    }
+    if input_chars.peek().is_some() {
+        return None;
+    }
 
-        if current_input_char == current_path_char {
-            match_in_progress = true;
-            match input_chars.next() {
-                Some(c) => {
-                    current_input_char = c;
-                },
-                None => return true
+    // `word_starts[j]` is true if a match may begin at path character `j`, either because it begins
+    // a word or because it is a non-alphanumeric character, which always may be matched.
+    let original: Vec<char> = original.chars().collect();
+    let word_starts: Vec<bool> = word_starts(&original, &options.word_boundaries)
+        .iter()
+        .zip(&path)
+        .map(|(word_start, c)| *word_start || !c.is_alphanumeric())
+        .collect();
+    let filename_start = path.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+
+    // `scores[i][j]` is the best score for matching the first `i + 1` input characters with input
+    // character `i` matched at path character `j`, or `None` if there's no valid way to do that.
+    // Matching an alphanumeric character that does not begin a word is only valid directly after a
+    // match of the previous input character, which is how matches continue inside a word.
+    // `matched_from[i][j]` is where input character `i - 1` was matched for that best score.
+    let mut scores: Vec<Vec<Option<i64>>> = vec![vec![None; path.len()]; input.len()];
+    let mut matched_from: Vec<Vec<usize>> = vec![vec![0; path.len()]; input.len()];
+    for i in 0..input.len() {
+        // The best score for input character `i - 1` matched anywhere before path character `j`, and where.
+        let mut best_before: Option<i64> = None;
+        let mut best_before_at = 0;
+        for j in 0..path.len() {
+            let previous = if i > 0 && j > 0 { scores[i - 1][j - 1] } else { None };
+            if path[j] == input[i] {
+                let mut score = SCORE_MATCH;
+                if j >= filename_start {
+                    score += BONUS_FILENAME;
+                }
+                let consecutive = previous.map(|p| p + score + BONUS_CONSECUTIVE);
+                let jumped = if !word_starts[j] {
+                    None
+                } else if i == 0 {
+                    Some(score + BONUS_WORD_START)
+                } else {
+                    best_before.map(|p| p + score + BONUS_WORD_START)
+                };
+                if consecutive >= jumped {
+                    scores[i][j] = consecutive;
+                    matched_from[i][j] = j.saturating_sub(1);
+                } else {
+                    scores[i][j] = jumped;
+                    matched_from[i][j] = best_before_at;
+                }
+            }
+            if previous > best_before {
+                best_before = previous;
+                best_before_at = j - 1;
             }
-        } else if is_alphanumeric {
-            matching_current_word = false;
         }
     }
+
+    // Find the best place the last input character matched and walk back to recover the rest.
+    let last = input.len() - 1;
+    let (end, score) = scores[last].iter()
+        .enumerate()
+        .filter_map(|(j, s)| s.map(|s| (j, s)))
+        .max_by_key(|&(j, s)| (s, cmp::Reverse(j)))?;
+    let mut positions = vec![end; input.len()];
+    for i in (1..input.len()).rev() {
+        positions[i - 1] = matched_from[i][positions[i]];
+    }
+    Some(Match {
+        score: score - path.len() as i64 * PENALTY_PATH_CHAR,
+        positions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fuzzy(pattern: &str, path: &str) -> Option<Match> {
+        let options = super::super::Options::for_tests();
+        SearchContext::fuzzy(pattern, &options).match_path(path::Path::new(path))
+    }
+
+    fn score(pattern: &str, path: &str) -> i64 {
+        fuzzy(pattern, path).unwrap_or_else(|| panic!("{} doesn't match {}", pattern, path)).score
+    }
+
+    #[test]
+    fn matches_start_at_words() {
+        assert_eq!(fuzzy("shared", "src/haskell/red.hs").map(|m| m.positions), Some(vec![0, 4, 5, 12, 13, 14]));
+        assert_eq!(fuzzy("mrs", "src/main.rs").map(|m| m.positions), Some(vec![4, 9, 10]));
+        assert_eq!(fuzzy("Catom", "Cargo.toml").map(|m| m.positions), Some(vec![0, 1, 6, 7, 8]));
+        assert_eq!(fuzzy("PSE", "patch/PatchSyntaxException.java").map(|m| m.positions), Some(vec![6, 11, 17]));
+        // `ain` doesn't start a word.
+        assert!(fuzzy("ain", "src/main.rs").is_none());
+        assert!(fuzzy("mx", "src/main.rs").is_none());
+    }
+
+    #[test]
+    fn later_words_are_tried_when_an_earlier_one_fails() {
+        // `s` could start at `src` but only `sample` continues with `am`.
+        assert_eq!(fuzzy("sam", "src/sample").map(|m| m.positions), Some(vec![4, 5, 6]));
+        assert_eq!(fuzzy("foo.js", "src/bar/foo.json").map(|m| m.positions), Some(vec![8, 9, 10, 11, 12, 13]));
+    }
+
+    #[test]
+    fn ranking() {
+        // Consecutive matches in the file name beat the same characters spread over directories.
+        assert!(score("foo.js", "src/bar/foo.json") > score("foo.js", "src/foo/bar.js"));
+        // A match at the start of the file name beats one at the start of a directory.
+        assert!(score("main", "src/main.rs") > score("main", "main/src.rs"));
+        // Shorter paths win ties.
+        assert!(score("rs", "src/main.rs") > score("rs", "src/interactive.rs"));
+        assert!(score("rs", "src/main.rs") > score("rs", ".git/hooks/pre-rebase.sample"));
+        assert!(score("rs", "src/main.rs") > score("rs", "deeply/nested/directory/of/rust/files/main.rs"));
+    }
+
+    #[test]
+    fn case() {
+        assert!(fuzzy("cargo", "Cargo.toml").is_none());
+        let options = super::super::Options {
+            smart_case: true,
+            ..super::super::Options::for_tests()
+        };
+        let smart = |pattern: &str| SearchContext::fuzzy(pattern, &options).match_path(path::Path::new("Cargo.toml")).is_some();
+        assert!(smart("cargo"));
+        assert!(smart("Cargo"));
+        assert!(!smart("CArgo"));
+    }
 }
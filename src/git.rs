@@ -0,0 +1,284 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::path;
+
+pub struct Repository {
+    // The directory checked out files live in, the one containing `.git`.
+    pub work_tree: path::PathBuf,
+    // The directory containing `info/exclude` and `config`. For linked worktrees this is the main
+    // repository's git directory.
+    pub git_dir: path::PathBuf,
+}
+
+impl Repository {
+
+    pub fn discover (dir: &path::Path) -> Option<Repository> {
+        //! Finds the repository `dir` is in by looking for `.git` in it and each of its parents.
+        let dir = dir.canonicalize().ok()?;
+        for ancestor in dir.ancestors() {
+            let dot_git = ancestor.join(".git");
+            if dot_git.is_dir() {
+                return Some(Repository {
+                    work_tree: ancestor.to_path_buf(),
+                    git_dir: dot_git,
+                });
+            }
+            if dot_git.is_file() {
+                // Linked worktrees and submodules have a `.git` file pointing at the real git directory.
+                let git_dir = read_gitdir_file(&dot_git)?;
+                return Some(Repository {
+                    work_tree: ancestor.to_path_buf(),
+                    git_dir: common_dir(&git_dir),
+                });
+            }
+        }
+        None
+    }
+
+    pub fn config_value (&self, section: &str, key: &str) -> Option<String> {
+        //! Reads a value from git's system, global and repository config files, later files win.
+        let mut files = global_config_files();
+        files.push(self.git_dir.join("config"));
+        let mut value = None;
+        for file in files {
+            if let Ok(Some(v)) = read_config_value(&file, section, key) {
+                value = Some(v);
+            }
+        }
+        value
+    }
+
+    pub fn ignores_case (&self) -> bool {
+        //! Whether `core.ignorecase` is set, as git does on case insensitive file systems.
+        self.config_value("core", "ignorecase").is_some_and(|v| ["true", "yes", "on", "1"].contains(&v.to_lowercase().as_str()))
+    }
+
+    pub fn excludes_file (&self) -> Option<path::PathBuf> {
+        //! Returns `core.excludesFile` or git's default of `$XDG_CONFIG_HOME/git/ignore`.
+        match self.config_value("core", "excludesfile") {
+            Some(f) => Some(expand_home(&f)),
+            None => xdg_config_home().map(|c| c.join("git").join("ignore")),
+        }
+    }
+}
+
+fn read_gitdir_file (dot_git: &path::Path) -> Option<path::PathBuf> {
+    let contents = fs::read_to_string(dot_git).ok()?;
+    let git_dir = contents.trim().strip_prefix("gitdir:")?.trim();
+    let git_dir = path::Path::new(git_dir);
+    if git_dir.is_absolute() {
+        return Some(git_dir.to_path_buf());
+    }
+    dot_git.parent().map(|p| p.join(git_dir))
+}
+
+fn common_dir (git_dir: &path::Path) -> path::PathBuf {
+    match fs::read_to_string(git_dir.join("commondir")) {
+        Ok(c) => git_dir.join(c.trim()),
+        Err(_) => git_dir.to_path_buf(),
+    }
+}
+
+fn home_dir () -> Option<path::PathBuf> {
+    env::var_os("HOME").map(path::PathBuf::from)
+}
+
+fn xdg_config_home () -> Option<path::PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(c) if !c.is_empty() => Some(path::PathBuf::from(c)),
+        _ => home_dir().map(|h| h.join(".config")),
+    }
+}
+
+fn expand_home (file: &str) -> path::PathBuf {
+    if let Some(rest) = file.strip_prefix("~/") {
+        if let Some(home) = home_dir() {
+            return home.join(rest);
+        }
+    }
+    path::PathBuf::from(file)
+}
+
+fn global_config_files () -> Vec<path::PathBuf> {
+    let mut files = vec![path::PathBuf::from("/etc/gitconfig")];
+    if let Some(c) = xdg_config_home() {
+        files.push(c.join("git").join("config"));
+    }
+    if let Some(h) = home_dir() {
+        files.push(h.join(".gitconfig"));
+    }
+    files
+}
+
+fn read_config_value (file: &path::Path, section: &str, key: &str) -> Result<Option<String>, io::Error> {
+    //! A minimal reader for git's ini style config files. Section and key names are case
+    //! insensitive, the last value for a key wins. Includes are not followed.
+    let f = fs::File::open(file)?;
+    let mut in_section = false;
+    let mut value = None;
+    for line in io::BufReader::new(f).lines() {
+        let line = line?;
+        let mut line = line.trim();
+        if line.starts_with('[') {
+            let end = match line.find(']') {
+                Some(e) => e,
+                None => continue,
+            };
+            in_section = line[1..end].trim().eq_ignore_ascii_case(section);
+            line = line[end + 1..].trim();
+        }
+        if !in_section || line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let (name, v) = match line.find('=') {
+            Some(i) => (line[..i].trim(), parse_config_value(&line[i + 1..])),
+            // A key without a value is a boolean true.
+            None => (line, String::from("true")),
+        };
+        if name.eq_ignore_ascii_case(key) {
+            value = Some(v);
+        }
+    }
+    Ok(value)
+}
+
+fn parse_config_value (raw: &str) -> String {
+    //! Strips comments, quotes and backslash escapes from a config value.
+    let mut value = String::new();
+    let mut quoted = false;
+    // The length of the value without the unquoted whitespace at its end.
+    let mut kept = 0;
+    let mut chars = raw.trim_start().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => {
+                match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(e) => value.push(e),
+                    None => (),
+                }
+                kept = value.len();
+            },
+            '#' | ';' if !quoted => break,
+            c => {
+                value.push(c);
+                if quoted || !c.is_whitespace() {
+                    kept = value.len();
+                }
+            },
+        }
+    }
+    value.truncate(kept);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config (name: &str, contents: &str) -> path::PathBuf {
+        let file = super::super::test_dir(name).join("config");
+        fs::write(&file, contents).unwrap();
+        file
+    }
+
+    fn value (contents: &str, section: &str, key: &str) -> Option<String> {
+        read_config_value(&config("git_config_value", contents), section, key).unwrap()
+    }
+
+    #[test]
+    fn config_values () {
+        assert_eq!(parse_config_value("  plain  "), "plain");
+        assert_eq!(parse_config_value("\"  quoted; #kept  \""), "  quoted; #kept  ");
+        assert_eq!(parse_config_value("a\"b c\"d"), "ab cd");
+        assert_eq!(parse_config_value("a\\\"b\\\\c\\td\\ne"), "a\"b\\c\td\ne");
+        assert_eq!(parse_config_value("value ; comment"), "value");
+        assert_eq!(parse_config_value("value# comment"), "value");
+        assert_eq!(parse_config_value("\"quoted\" # comment"), "quoted");
+        assert_eq!(parse_config_value("\"unterminated "), "unterminated ");
+        assert_eq!(parse_config_value("trailing\\ "), "trailing ");
+        assert_eq!(parse_config_value(""), "");
+    }
+
+    #[test]
+    fn config_sections_and_keys () {
+        let contents = "\
+# excludesFile = /commented
+[user]
+    excludesFile = /wrong/section
+[core \"sub\"]
+    excludesFile = /subsection
+[Core]
+    ExcludesFile = \"/quoted path\" ; comment
+    ignorecase
+[core] bare = yes
+";
+        assert_eq!(value(contents, "core", "excludesfile"), Some("/quoted path".to_string()));
+        assert_eq!(value(contents, "CORE", "EXCLUDESFILE"), Some("/quoted path".to_string()));
+        // Keys without values are true, and keys can follow the section on its line.
+        assert_eq!(value(contents, "core", "ignorecase"), Some("true".to_string()));
+        assert_eq!(value(contents, "core", "bare"), Some("yes".to_string()));
+        assert_eq!(value(contents, "user", "excludesfile"), Some("/wrong/section".to_string()));
+        assert_eq!(value(contents, "core", "missing"), None);
+        // The last value wins.
+        assert_eq!(value("[core]\na = 1\n[other]\na = 2\n[core]\na = 3\n", "core", "a"), Some("3".to_string()));
+        assert!(read_config_value(path::Path::new("/nonexistent/config"), "core", "a").is_err());
+    }
+
+    #[test]
+    fn discover_a_repository () {
+        let dir = super::super::test_dir("git_discover").canonicalize().unwrap();
+        fs::create_dir_all(dir.join("repo/.git")).unwrap();
+        fs::create_dir_all(dir.join("repo/src/deep")).unwrap();
+        let repository = Repository::discover(&dir.join("repo/src/deep")).unwrap();
+        assert_eq!(repository.work_tree, dir.join("repo"));
+        assert_eq!(repository.git_dir, dir.join("repo/.git"));
+        assert!(Repository::discover(&dir).is_none());
+    }
+
+    #[test]
+    fn discover_a_linked_worktree () {
+        let dir = super::super::test_dir("git_worktree").canonicalize().unwrap();
+        let linked = dir.join("main/.git/worktrees/wt");
+        fs::create_dir_all(&linked).unwrap();
+        fs::write(linked.join("commondir"), "../..\n").unwrap();
+        fs::create_dir_all(dir.join("wt/src")).unwrap();
+        fs::write(dir.join("wt/.git"), "gitdir: ../main/.git/worktrees/wt\n").unwrap();
+        let repository = Repository::discover(&dir.join("wt/src")).unwrap();
+        assert_eq!(repository.work_tree, dir.join("wt"));
+        // Ignore rules and config come from the main repository.
+        assert_eq!(repository.git_dir.canonicalize().unwrap(), dir.join("main/.git"));
+    }
+
+    #[test]
+    fn discover_a_submodule () {
+        let dir = super::super::test_dir("git_submodule").canonicalize().unwrap();
+        let module = dir.join("super/.git/modules/sub");
+        fs::create_dir_all(&module).unwrap();
+        fs::create_dir_all(dir.join("super/sub")).unwrap();
+        fs::write(dir.join("super/sub/.git"), format!("gitdir: {}\n", module.display())).unwrap();
+        let repository = Repository::discover(&dir.join("super/sub")).unwrap();
+        assert_eq!(repository.work_tree, dir.join("super/sub"));
+        assert_eq!(repository.git_dir, module);
+        // A `.git` file that doesn't point anywhere isn't a repository.
+        fs::create_dir_all(dir.join("broken")).unwrap();
+        fs::write(dir.join("broken/.git"), "not a gitdir line\n").unwrap();
+        assert!(Repository::discover(&dir.join("broken")).is_none());
+    }
+
+    #[test]
+    fn excludes_file_in_home () {
+        let dir = super::super::test_dir("git_excludes_file").canonicalize().unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git/config"), "[core]\n\texcludesFile = ~/my ignores\n").unwrap();
+        let repository = Repository::discover(&dir).unwrap();
+        let home = path::PathBuf::from(env::var_os("HOME").unwrap());
+        assert_eq!(repository.excludes_file(), Some(home.join("my ignores")));
+        assert_eq!(expand_home("/absolute/~/ignore"), path::PathBuf::from("/absolute/~/ignore"));
+        assert_eq!(expand_home("~user/ignore"), path::PathBuf::from("~user/ignore"));
+    }
+}
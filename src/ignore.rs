@@ -1,15 +1,54 @@
 use glob::Pattern;
 use glob::PatternError;
 use glob::MatchOptions;
+use regex::Regex;
+use regex::RegexBuilder;
+use std::ffi::OsStr;
 use std::io;
 use std::io::BufRead;
 use std::path;
 use std::fs;
+use git;
 use log::*;
 
+// The ignore files read in every directory unless `--ignore-files` names others. Their rules are
+// added in this order and later rules win, so within a directory version control's ignore files
+// have the least say, then `.ignore` files many search tools share, then fd's and fe's own.
+// Deeper directories' ignore files still win over any of their parents'.
+pub const IGNORE_FILES: &[&str] = &[".hgignore", ".gitignore", ".ignore", ".fdignore", ".feignore"];
+
+// Ignore files of version control systems, which `--no-ignore-vcs` skips.
+const VCS_IGNORE_FILES: &[&str] = &[".hgignore", ".gitignore"];
+
+// How an ignore file is written, told apart by its name.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    // .gitignore, and everything else that isn't one of the others.
+    Git,
+    // .hgignore, regular expressions unless a `syntax: glob` line says otherwise.
+    Mercurial,
+    // .dockerignore, every pattern is relative to the directory it's in.
+    Docker,
+}
+
 #[derive(Clone)]
 pub struct RuleSet {
+    // Rules in the order they were read, parent directories' rules first. The last matching rule wins.
     rules: Vec<RuleSetPattern>,
+    // Rules from `--exclude` and `--include`, which win over every ignore file.
+    overrides: Vec<RuleSetPattern>,
+    // Whether rules match regardless of case, with `--ignore-file-case-insensitive` or in git
+    // repositories with `core.ignorecase` set.
+    case_insensitive: bool,
+}
+
+// The rule that decides whether a path is ignored, for `--explain`.
+pub struct Decision {
+    // Where the rule is written, as `file:line`, or the option it came from.
+    pub source: String,
+    pub rule: String,
+    // Whether the path is ignored, or re-included by a rule starting with `!`.
+    pub ignored: bool,
 }
 
 pub enum IgnoreError {
@@ -18,94 +57,710 @@ pub enum IgnoreError {
 
 pub enum RuleSetError {
     Pattern(PatternError),
+    Regex(::regex::Error),
     NoLength,
 }
 
+#[derive(Clone)]
+enum Matcher {
+    Glob(Pattern),
+    // Regular expressions from .hgignore files, which match anywhere in the path relative to `base`
+    // unless they start with `^`.
+    Regex(Regex),
+}
+
 #[derive(Clone)]
 struct RuleSetPattern {
-    pattern: Pattern,
+    // The rule as written in the ignore file, for logging.
+    rule: String,
+    // The ignore file and line the rule is on, for `--explain`.
+    source: String,
+    matcher: Matcher,
+    // The directory containing the ignore file, anchored patterns match paths relative to it.
+    base: path::PathBuf,
+    // Prepended to paths relative to `base`. Used when the ignore file is above the search root,
+    // `base` is then the search root and this is the search root's path relative to the ignore file.
+    base_offset: path::PathBuf,
+    // Rules starting with `!` re-include paths excluded by an earlier rule.
+    negated: bool,
+    // Rules ending with `/` only match directories.
     is_dir: bool,
+    // Rules with a `/` at the start or in the middle match the path relative to `base`, all other
+    // rules match the file name at any depth.
+    anchored: bool,
 }
 
 impl RuleSetPattern {
-    fn new (new_path: &str) -> Result<RuleSetPattern, RuleSetError> {
-        let mut path = new_path.trim();
+    fn new (new_path: &str, base: &path::Path, base_offset: &path::Path) -> Result<RuleSetPattern, RuleSetError> {
+        //! Parses a single line of a .gitignore file following https://git-scm.com/docs/gitignore.
+        let mut path = trim_unescaped_trailing_spaces(new_path);
+        let rule = String::from(path);
+        let negated = path.starts_with('!');
+        if negated {
+            path = &path[1..];
+        }
         let is_dir = path.ends_with('/');
         if is_dir {
             path = &path[..path.len() - 1]
         }
-        if path.len() == 0 {
+        let anchored = path.contains('/');
+        if path.starts_with('/') {
+            path = &path[1..];
+        }
+        if path.is_empty() {
             return Err(RuleSetError::NoLength);
         }
-        let pattern = Pattern::new(path).map_err(RuleSetError::Pattern)?;
+        let pattern = Pattern::new(&to_glob(path)).map_err(RuleSetError::Pattern)?;
         Ok(RuleSetPattern {
-            pattern: pattern,
-            is_dir: false,
+            rule,
+            source: String::new(),
+            matcher: Matcher::Glob(pattern),
+            base: base.to_path_buf(),
+            base_offset: base_offset.to_path_buf(),
+            negated,
+            is_dir,
+            anchored,
         })
     }
+
+    fn new_mercurial (line: &str, syntax: &str, base: &path::Path, base_offset: &path::Path, case_insensitive: bool) -> Result<RuleSetPattern, RuleSetError> {
+        //! Parses a line of a .hgignore file following https://www.mercurial-scm.org/doc/hgignore.5.html.
+        //! A `re:`, `glob:` or `rootglob:` prefix overrides the `syntax` of the section the line is in.
+        let (syntax, pattern) = match line.split_once(':') {
+            Some((prefix, pattern)) if ["re", "regexp", "glob", "relglob", "rootglob"].contains(&prefix) => (prefix, pattern),
+            _ => (syntax, line),
+        };
+        if pattern.is_empty() {
+            return Err(RuleSetError::NoLength);
+        }
+        match syntax {
+            "re" | "regexp" => Ok(RuleSetPattern {
+                rule: String::from(line),
+                source: String::new(),
+                matcher: Matcher::Regex(RegexBuilder::new(pattern).case_insensitive(case_insensitive).build().map_err(RuleSetError::Regex)?),
+                base: base.to_path_buf(),
+                base_offset: base_offset.to_path_buf(),
+                negated: false,
+                is_dir: false,
+                anchored: true,
+            }),
+            // Mercurial's globs aren't rooted and don't negate, globs with a `/` match at any depth.
+            _ => {
+                let pattern = pattern.strip_prefix('!').map_or_else(|| pattern.to_string(), |p| format!("\\!{}", p));
+                let rule = if syntax == "rootglob" {
+                    format!("/{}", pattern)
+                } else if pattern.contains('/') {
+                    format!("**/{}", pattern)
+                } else {
+                    pattern
+                };
+                let mut r = RuleSetPattern::new(&rule, base, base_offset)?;
+                r.rule = String::from(line);
+                Ok(r)
+            },
+        }
+    }
+
+    fn new_docker (line: &str, base: &path::Path, base_offset: &path::Path) -> Result<RuleSetPattern, RuleSetError> {
+        //! Parses a line of a .dockerignore file. Its patterns are always relative to the directory
+        //! it's in and match files and directories alike.
+        let (negation, pattern) = match line.strip_prefix('!') {
+            Some(p) => ("!", p.trim()),
+            None => ("", line),
+        };
+        let pattern = pattern.trim_start_matches("./").trim_start_matches('/').trim_end_matches('/');
+        if pattern.is_empty() {
+            return Err(RuleSetError::NoLength);
+        }
+        let mut r = RuleSetPattern::new(&format!("{}/{}", negation, pattern), base, base_offset)?;
+        r.rule = String::from(line);
+        Ok(r)
+    }
+
+    fn matches (&self, path: &path::Path, is_dir: bool, case_insensitive: bool) -> bool {
+        if self.is_dir && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(r) => self.base_offset.join(r),
+            Err(_) => return false,
+        };
+        let match_options = MatchOptions {
+            case_sensitive: !case_insensitive,
+            require_literal_separator: true,
+            require_literal_leading_dot: false
+        };
+        // Paths that aren't UTF-8 are matched lossily, ignore files rarely mention them.
+        let pattern = match self.matcher {
+            Matcher::Glob(ref p) => p,
+            Matcher::Regex(ref r) => return r.is_match(&relative.to_string_lossy()),
+        };
+        if self.anchored {
+            return pattern.matches_with(&relative.to_string_lossy(), match_options);
+        }
+        match relative.file_name() {
+            Some(name) => pattern.matches_with(&name.to_string_lossy(), match_options),
+            None => false,
+        }
+    }
+}
+
+fn trim_unescaped_trailing_spaces (line: &str) -> &str {
+    //! Trailing spaces are ignored unless they are escaped with a backslash.
+    let mut end = line.trim_end_matches(' ').len();
+    if end < line.len() && line[..end].ends_with('\\') {
+        end += 1;
+    }
+    &line[..end]
+}
+
+fn strip_mercurial_comment (line: &str) -> String {
+    //! Comments in .hgignore files start at a `#` anywhere in a line, `\#` is a literal `#`.
+    //! Trailing whitespace is ignored.
+    let mut stripped = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('#') => stripped.push('#'),
+                Some(escaped) => {
+                    stripped.push('\\');
+                    stripped.push(escaped);
+                },
+                None => stripped.push('\\'),
+            },
+            '#' => break,
+            c => stripped.push(c),
+        }
+    }
+    stripped.trim_end().to_string()
+}
+
+fn to_glob (rule: &str) -> String {
+    //! Converts a .gitignore pattern to a glob pattern. Backslash escapes become single character
+    //! classes and `**` that isn't a whole path component is an ordinary `*`, as it is in git.
+    let chars: Vec<char> = rule.chars().collect();
+    let mut glob = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                match chars[i] {
+                    '*' | '?' | '[' | ']' => {
+                        glob.push('[');
+                        glob.push(chars[i]);
+                        glob.push(']');
+                    },
+                    c => glob.push(c),
+                }
+            },
+            '*' => {
+                let start = i;
+                while i + 1 < chars.len() && chars[i + 1] == '*' {
+                    i += 1;
+                }
+                let whole_component = (start == 0 || chars[start - 1] == '/')
+                    && (i + 1 == chars.len() || chars[i + 1] == '/');
+                if i > start && whole_component {
+                    glob.push_str("**");
+                } else {
+                    glob.push('*');
+                }
+            },
+            c => glob.push(c),
+        }
+        i += 1;
+    }
+    glob
+}
+
+pub fn ignore_file_names (options: &super::Options) -> Vec<&str> {
+    //! The ignore files read in every directory, lowest precedence first. `--no-ignore` reads none
+    //! and `--no-ignore-vcs` skips those of version control systems.
+    if options.no_ignore {
+        return vec![];
+    }
+    options.ignore_files.iter()
+        .map(String::as_str)
+        .filter(|name| !options.no_ignore_vcs || !VCS_IGNORE_FILES.contains(name))
+        .collect()
+}
+
+pub fn is_ignore_file (name: &OsStr, options: &super::Options) -> bool {
+    //! Whether files named `name` are read as ignore files in every directory.
+    !options.no_ignore && options.ignore_files.iter()
+        .any(|n| OsStr::new(n) == name && (!options.no_ignore_vcs || !VCS_IGNORE_FILES.contains(&n.as_str())))
 }
 
 impl RuleSet {
 
-    pub fn new_default() -> RuleSet {
+    pub fn new_default (root: &path::Path, options: &super::Options) -> RuleSet {
         let mut rules: Vec<RuleSetPattern> = vec![];
-        match RuleSetPattern::new(".git/") {
-            Ok(r) => rules.push(r),
-            _ => (),
+        if options.git_ignores() {
+            if let Ok(mut r) = RuleSetPattern::new(".git/", root, path::Path::new("")) {
+                r.source = String::from("fe's rule for git's own files");
+                rules.push(r);
+            }
         }
         RuleSet {
-            rules: rules
+            rules,
+            overrides: vec![],
+            case_insensitive: options.ignore_file_case_insensitive,
+        }
+    }
+
+    pub fn new_root (root: &path::Path, options: &super::Options) -> RuleSet {
+        //! Creates the rule set for the search root, with the rules from `root_ignore_files`.
+        let mut rule_set = RuleSet::new_default(root, options);
+        if !rule_set.case_insensitive && options.git_ignores() {
+            rule_set.case_insensitive = git::Repository::discover(root).is_some_and(|r| r.ignores_case());
         }
+        for rule in &options.overrides {
+            match RuleSetPattern::new(rule, root, path::Path::new("")) {
+                Ok(mut r) => {
+                    r.source = String::from(if r.negated { "--include" } else { "--exclude" });
+                    rule_set.overrides.push(r);
+                },
+                Err(RuleSetError::Pattern(e)) => v(format!("Skipping invalid pattern {}: {}", rule, e), options),
+                Err(RuleSetError::Regex(_)) | Err(RuleSetError::NoLength) => (),
+            }
+        }
+        for (ignore_path, base_offset) in RuleSet::root_ignore_files(root, options) {
+            match RuleSet::load(&ignore_path, root, &base_offset, rule_set.case_insensitive, options) {
+                Ok(r) => rule_set.rules.extend(r.rules),
+                Err(IgnoreError::Io(e)) => {
+                    if e.kind() != io::ErrorKind::NotFound {
+                        v(format!("Failed to read {:?}: {}", ignore_path, e), options);
+                    }
+                },
+            }
+        }
+        rule_set
     }
 
-    pub fn new (ignore_path: &path::Path, options: &super::Options) -> Result<RuleSet, IgnoreError> {
+    pub fn root_ignore_files (root: &path::Path, options: &super::Options) -> Vec<(path::PathBuf, path::PathBuf)> {
+        //! If the root is in a git or Mercurial repository, returns the ignore files that apply to
+        //! it from outside of it whether they exist or not, lowest precedence first: for git the
+        //! `core.excludesFile` or global ignore file and `.git/info/exclude`, then the ignore files
+        //! in the root's parent directories up to the top of the repository, where Mercurial keeps
+        //! its .hgignore. Each comes with the root's path relative to the directory its rules are
+        //! relative to. `--no-ignore-vcs` leaves out git's and Mercurial's files.
+        if options.no_ignore {
+            return vec![];
+        }
+        let canonical_root = match root.canonicalize() {
+            Ok(r) => r,
+            Err(_) => return vec![],
+        };
+        let root_offset = |dir: &path::Path| canonical_root.strip_prefix(dir).map(path::Path::to_path_buf).unwrap_or_default();
+
+        let mut ignore_files = vec![];
+        let top = match git::Repository::discover(root) {
+            Some(repository) => {
+                v(format!("Found a git repository at {:?}", repository.work_tree), options);
+                if options.git_ignores() {
+                    if let Some(excludes_file) = repository.excludes_file() {
+                        ignore_files.push((excludes_file, root_offset(&repository.work_tree)));
+                    }
+                    ignore_files.push((repository.git_dir.join("info").join("exclude"), root_offset(&repository.work_tree)));
+                }
+                repository.work_tree
+            },
+            None if is_ignore_file(OsStr::new(".hgignore"), options) => {
+                match canonical_root.ancestors().find(|a| a.join(".hg").is_dir()) {
+                    Some(top) => {
+                        v(format!("Found a Mercurial repository at {:?}", top), options);
+                        top.to_path_buf()
+                    },
+                    None => return vec![],
+                }
+            },
+            None => return vec![],
+        };
+        let mut parents: Vec<&path::Path> = canonical_root.ancestors()
+            .skip(1)
+            .take_while(|a| a.starts_with(&top))
+            .collect();
+        parents.reverse();
+        for parent in parents {
+            for name in ignore_file_names(options) {
+                ignore_files.push((parent.join(name), root_offset(parent)));
+            }
+        }
+        ignore_files
+    }
+
+    fn load (ignore_path: &path::Path, base: &path::Path, base_offset: &path::Path, case_insensitive: bool, options: &super::Options) -> Result<RuleSet, IgnoreError> {
 
         let f = fs::File::open(ignore_path).map_err(IgnoreError::Io)?;
         v(format!("Found {:?} an ignore file.", ignore_path), options);
 
+        let format = match ignore_path.file_name().and_then(OsStr::to_str) {
+            Some(".hgignore") => Format::Mercurial,
+            Some(".dockerignore") => Format::Docker,
+            _ => Format::Git,
+        };
+        // Mercurial reads regular expressions until a `syntax:` line says otherwise.
+        let mut syntax = String::from("regexp");
+
         let buffer = io::BufReader::new(&f);
         let mut rules: Vec<RuleSetPattern> = vec![];
-        for line in buffer.lines() {
+        for (number, line) in buffer.lines().enumerate() {
             let l = line.map_err(IgnoreError::Io)?;
-            if l.starts_with('#') {
-                continue;
-            }
-            let r = match RuleSetPattern::new(&l[..]) {
+            let parsed = match format {
+                Format::Git if l.starts_with('#') => continue,
+                Format::Git => RuleSetPattern::new(&l[..], base, base_offset),
+                Format::Mercurial => {
+                    let line = strip_mercurial_comment(&l);
+                    if let Some(s) = line.strip_prefix("syntax:") {
+                        syntax = String::from(s.trim());
+                        continue;
+                    }
+                    RuleSetPattern::new_mercurial(&line, &syntax, base, base_offset, case_insensitive)
+                },
+                Format::Docker if l.trim_start().starts_with('#') => continue,
+                Format::Docker => RuleSetPattern::new_docker(l.trim(), base, base_offset),
+            };
+            let r = match parsed {
                 Ok(r) => r,
-                _ => continue // TODO: support ! rule negations.
+                Err(RuleSetError::Pattern(e)) => {
+                    vv(format!("Skipping invalid rule {}: {}", l, e), options);
+                    continue;
+                },
+                Err(RuleSetError::Regex(e)) => {
+                    vv(format!("Skipping invalid rule {}: {}", l, e), options);
+                    continue;
+                },
+                Err(RuleSetError::NoLength) => continue,
             };
             v(format!("Found rule: {}.", l), options);
-            rules.push(r);
+            rules.push(RuleSetPattern {
+                source: format!("{}:{}", ignore_path.display(), number + 1),
+                ..r
+            });
         }
 
         Ok(RuleSet {
-            rules: rules
+            rules,
+            overrides: vec![],
+            case_insensitive,
         })
     }
 
     pub fn extend (rule_set: &RuleSet, ignore_path: &path::Path, options: &super::Options) -> Result<RuleSet, IgnoreError> {
-        let mut new_set = RuleSet::new(ignore_path, options)?;
-        new_set.rules.extend(rule_set.rules.clone());
-        Ok(new_set)
+        let base = ignore_path.parent().unwrap_or_else(|| path::Path::new(""));
+        let new_set = RuleSet::load(ignore_path, base, path::Path::new(""), rule_set.case_insensitive, options)?;
+        let mut rules = rule_set.rules.clone();
+        rules.extend(new_set.rules);
+        Ok(RuleSet {
+            rules,
+            overrides: rule_set.overrides.clone(),
+            case_insensitive: rule_set.case_insensitive,
+        })
     }
 
-    pub fn is_excluded (&self, path: &str, is_dir: bool, options: &super::Options) -> bool {
-        for rule_set_pattern in &self.rules {
-            if rule_set_pattern.is_dir && !is_dir {
+    pub fn is_excluded (&self, path: &path::Path, is_dir: bool, options: &super::Options) -> bool {
+        //! Rules from deeper ignore files and later lines override earlier ones, so the rules are
+        //! checked last to first and the first match decides. Overrides are checked before all of them.
+        for rule_set_pattern in self.overrides.iter().rev().chain(self.rules.iter().rev()) {
+            if !rule_set_pattern.matches(path, is_dir, self.case_insensitive) {
+                vv(format!("{:?} is not ignored because it doesn't match {}", path, rule_set_pattern.rule), options);
                 continue;
             }
-            let match_options = MatchOptions {
-                case_sensitive: true, // .gitignore is case insensitive, but fe isn't.
-                require_literal_separator: false,
-                require_literal_leading_dot: false
-            };
-            if rule_set_pattern.pattern.matches_with(path, match_options) {
-                v(format!("{} is ignored because it matches {}", path, rule_set_pattern.pattern), options);
-                return true;
-            } else {
-                vv(format!("{} is not ignored because it doesn't match {}", path, rule_set_pattern.pattern), options);
+            if rule_set_pattern.negated {
+                v(format!("{:?} is not ignored because it matches {}", path, rule_set_pattern.rule), options);
+                return false;
             }
+            v(format!("{:?} is ignored because it matches {}", path, rule_set_pattern.rule), options);
+            return true;
         }
         false
     }
-}
\ No newline at end of file
+
+    pub fn decide (&self, path: &path::Path, is_dir: bool) -> Option<Decision> {
+        //! Finds the rule `is_excluded` goes by for `path`, if any rule matches it.
+        self.overrides.iter().rev()
+            .chain(self.rules.iter().rev())
+            .find(|rule_set_pattern| rule_set_pattern.matches(path, is_dir, self.case_insensitive))
+            .map(|rule_set_pattern| Decision {
+                source: rule_set_pattern.source.clone(),
+                rule: rule_set_pattern.rule.clone(),
+                ignored: !rule_set_pattern.negated,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use find;
+
+    fn rule_set (lines: &[&str]) -> RuleSet {
+        RuleSet {
+            rules: lines.iter()
+                .map(|l| match RuleSetPattern::new(l, path::Path::new("/repo"), path::Path::new("")) {
+                    Ok(r) => r,
+                    Err(_) => panic!("{} isn't a valid rule", l),
+                })
+                .collect(),
+            overrides: vec![],
+            case_insensitive: false,
+        }
+    }
+
+    fn ignores (lines: &[&str], path: &str, is_dir: bool) -> bool {
+        rule_set(lines).decide(&path::Path::new("/repo").join(path), is_dir).is_some_and(|d| d.ignored)
+    }
+
+    #[test]
+    fn names_match_at_any_depth () {
+        assert!(ignores(&["*.o"], "a.o", false));
+        assert!(ignores(&["*.o"], "src/deep/b.o", false));
+        assert!(!ignores(&["*.o"], "a.oo", false));
+        assert!(ignores(&["target"], "crates/x/target", true));
+    }
+
+    #[test]
+    fn slashes_anchor () {
+        assert!(ignores(&["/build"], "build", true));
+        assert!(!ignores(&["/build"], "src/build", true));
+        assert!(ignores(&["doc/frotz"], "doc/frotz", false));
+        assert!(!ignores(&["doc/frotz"], "a/doc/frotz", false));
+        assert!(!ignores(&["doc/*.txt"], "doc/sub/a.txt", false));
+    }
+
+    #[test]
+    fn trailing_slashes_only_match_directories () {
+        assert!(ignores(&["logs/"], "logs", true));
+        assert!(ignores(&["logs/"], "app/logs", true));
+        assert!(!ignores(&["logs/"], "logs", false));
+        assert!(ignores(&["/out/"], "out", true));
+        assert!(!ignores(&["/out/"], "src/out", true));
+    }
+
+    #[test]
+    fn last_matching_rule_wins () {
+        assert!(!ignores(&["*.log", "!keep.log"], "keep.log", false));
+        assert!(ignores(&["*.log", "!keep.log"], "other.log", false));
+        assert!(ignores(&["!keep.log", "*.log"], "keep.log", false));
+        assert!(!ignores(&["!keep.log"], "keep.log", false));
+        let decision = rule_set(&["*.log", "!keep.log"]).decide(path::Path::new("/repo/keep.log"), false);
+        assert!(decision.is_some_and(|d| !d.ignored && d.rule == "!keep.log"));
+    }
+
+    #[test]
+    fn double_stars () {
+        assert!(ignores(&["**/foo"], "foo", false));
+        assert!(ignores(&["**/foo"], "a/b/foo", false));
+        assert!(ignores(&["**/foo/bar"], "x/foo/bar", false));
+        assert!(ignores(&["a/**/b"], "a/b", false));
+        assert!(ignores(&["a/**/b"], "a/x/y/b", false));
+        assert!(ignores(&["abc/**"], "abc/x/y", false));
+        assert!(!ignores(&["abc/**"], "xabc/y", false));
+        // `**` that isn't a whole path component is an ordinary `*`.
+        assert!(ignores(&["/a**b"], "axxb", false));
+        assert!(!ignores(&["/a**b"], "ax/yb", false));
+    }
+
+    #[test]
+    fn wildcards_and_classes () {
+        assert!(ignores(&["file?.txt"], "file1.txt", false));
+        assert!(!ignores(&["file?.txt"], "file10.txt", false));
+        assert!(ignores(&["[ab].c"], "b.c", false));
+        assert!(!ignores(&["[ab].c"], "c.c", false));
+        assert!(!ignores(&["/a*"], "a/b", false));
+    }
+
+    #[test]
+    fn escapes () {
+        assert!(ignores(&["\\!important"], "!important", false));
+        assert!(ignores(&["\\#notes"], "#notes", false));
+        assert!(ignores(&["\\*"], "*", false));
+        assert!(!ignores(&["\\*"], "a", false));
+        assert!(ignores(&["\\?"], "?", false));
+        assert!(!ignores(&["\\?"], "a", false));
+    }
+
+    #[test]
+    fn trailing_spaces () {
+        assert!(ignores(&["bar   "], "bar", false));
+        assert!(!ignores(&["bar   "], "bar ", false));
+        assert!(ignores(&["foo\\ "], "foo ", false));
+        assert!(!ignores(&["foo\\ "], "foo", false));
+    }
+
+    #[test]
+    fn empty_rules () {
+        for line in &["", "   ", "/", "!", "!/"] {
+            assert!(matches!(RuleSetPattern::new(line, path::Path::new("/repo"), path::Path::new("")), Err(RuleSetError::NoLength)), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn rules_from_above_the_root () {
+        // A .gitignore in /repo applied to a search of /repo/sub, as `new_root` does.
+        let rule = match RuleSetPattern::new("sub/generated/", path::Path::new("/repo/sub"), path::Path::new("sub")) {
+            Ok(r) => r,
+            Err(_) => panic!("sub/generated/ isn't a valid rule"),
+        };
+        assert!(rule.matches(path::Path::new("/repo/sub/generated"), true, false));
+        assert!(!rule.matches(path::Path::new("/repo/sub/other"), true, false));
+    }
+
+    #[test]
+    fn case () {
+        let mut rules = rule_set(&["*.TXT"]);
+        assert!(rules.decide(path::Path::new("/repo/a.txt"), false).is_none());
+        rules.case_insensitive = true;
+        assert!(rules.decide(path::Path::new("/repo/a.txt"), false).is_some_and(|d| d.ignored));
+    }
+
+    fn with_overrides (overrides: &[&str], lines: &[&str]) -> RuleSet {
+        //! The rules of `lines` with `overrides` from `--exclude` and `--include`, as `main` writes them.
+        let mut options = super::super::Options::for_tests();
+        options.overrides = overrides.iter().map(|o| o.to_string()).collect();
+        let mut rules = RuleSet::new_root(path::Path::new("/repo"), &options);
+        rules.rules = rule_set(lines).rules;
+        rules
+    }
+
+    #[test]
+    fn excludes_beat_ignore_files () {
+        let rules = with_overrides(&["*.log"], &["!debug.log"]);
+        let decision = rules.decide(path::Path::new("/repo/debug.log"), false);
+        assert!(decision.is_some_and(|d| d.ignored && d.source == "--exclude"));
+        assert!(rules.decide(path::Path::new("/repo/debug.txt"), false).is_none());
+    }
+
+    #[test]
+    fn includes_re_include_ignored_paths () {
+        let rules = with_overrides(&["!generated"], &["generated/", "*.o"]);
+        let decision = rules.decide(path::Path::new("/repo/src/generated"), true);
+        assert!(decision.is_some_and(|d| !d.ignored && d.source == "--include"));
+        assert!(rules.decide(path::Path::new("/repo/a.o"), false).is_some_and(|d| d.ignored));
+        // Later overrides win, like later lines of an ignore file.
+        assert!(with_overrides(&["*.log", "!keep.log"], &[]).decide(path::Path::new("/repo/keep.log"), false).is_some_and(|d| !d.ignored));
+        assert!(with_overrides(&["!keep.log", "*.log"], &[]).decide(path::Path::new("/repo/keep.log"), false).is_some_and(|d| d.ignored));
+    }
+
+    #[test]
+    fn overrides_prune_directories () {
+        let dir = super::super::test_dir("overrides_prune_directories");
+        for file in &["build/out.o", "target/debug/fe", "src/main.rs"] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        let walk = |overrides: &[&str]| {
+            let mut options = super::super::Options::for_tests();
+            options.search_dir = dir.to_string_lossy().into_owned();
+            options.overrides = overrides.iter().map(|o| o.to_string()).collect();
+            let (sender, receiver) = mpsc::channel();
+            find::walk_paths(&options, sender);
+            let mut paths: Vec<String> = receiver.iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect();
+            paths.sort();
+            paths
+        };
+        assert_eq!(walk(&[]), vec!["build", "build/out.o", "src", "src/main.rs"]);
+        // An excluded directory isn't walked into, and an included one is even when it's ignored.
+        assert_eq!(walk(&["build"]), vec!["src", "src/main.rs"]);
+        assert_eq!(walk(&["build/", "!target"]), vec!["src", "src/main.rs", "target", "target/debug", "target/debug/fe"]);
+    }
+
+    fn load_file (test: &str, name: &str, contents: &str) -> (path::PathBuf, RuleSet) {
+        //! Reads `contents` as an ignore file called `name` in a directory of its own.
+        let dir = super::super::test_dir(test);
+        fs::write(dir.join(name), contents).unwrap();
+        match RuleSet::load(&dir.join(name), &dir, path::Path::new(""), false, &super::super::Options::for_tests()) {
+            Ok(rules) => (dir, rules),
+            Err(IgnoreError::Io(e)) => panic!("{} can't be read: {}", name, e),
+        }
+    }
+
+    fn ignored_in (loaded: &(path::PathBuf, RuleSet), path: &str, is_dir: bool) -> bool {
+        loaded.1.decide(&loaded.0.join(path), is_dir).is_some_and(|d| d.ignored)
+    }
+
+    #[test]
+    fn mercurial_syntax_lines () {
+        let loaded = load_file("mercurial_syntax_lines", ".hgignore",
+                               "# Regular expressions to start with.\n\\.orig$\nsyntax: glob\n*.pyc\nlib/*.so\nsyntax:regexp\n^docs/_build\n");
+        assert!(ignored_in(&loaded, "a/b.orig", false));
+        assert!(!ignored_in(&loaded, "b.orig.txt", false));
+        // Globs without a slash match names, globs with one match at any depth.
+        assert!(ignored_in(&loaded, "x/y.pyc", false));
+        assert!(!ignored_in(&loaded, "x/ypyc", false));
+        assert!(ignored_in(&loaded, "lib/a.so", false));
+        assert!(ignored_in(&loaded, "vendor/lib/a.so", false));
+        assert!(!ignored_in(&loaded, "lib/a/b.so", false));
+        assert!(ignored_in(&loaded, "docs/_build/index.html", false));
+        let decision = loaded.1.decide(&loaded.0.join("x.pyc"), false);
+        assert!(decision.is_some_and(|d| d.rule == "*.pyc" && d.source.ends_with(".hgignore:4")));
+    }
+
+    #[test]
+    fn mercurial_prefixes () {
+        let loaded = load_file("mercurial_prefixes", ".hgignore",
+                               "glob:*.swp\nsyntax: glob\nre:^tmp/\nregexp:\\.bak$\nglob:*.o\nrootglob:*.log\nrelglob:cache\n");
+        // A prefix decides the syntax of its line whatever section it's in.
+        assert!(ignored_in(&loaded, "src/.a.swp", false));
+        assert!(ignored_in(&loaded, "tmp/x", false));
+        assert!(!ignored_in(&loaded, "a/tmp/x", false));
+        assert!(ignored_in(&loaded, "a/b.bak", false));
+        assert!(ignored_in(&loaded, "a/b.o", false));
+        assert!(ignored_in(&loaded, "x.log", false));
+        assert!(!ignored_in(&loaded, "a/x.log", false));
+        assert!(ignored_in(&loaded, "a/cache", true));
+        // Other words before a colon are part of the pattern.
+        let loaded = load_file("mercurial_prefixes", ".hgignore", "syntax: glob\nc:drive\n");
+        assert!(ignored_in(&loaded, "c:drive", false));
+    }
+
+    #[test]
+    fn mercurial_comments () {
+        let loaded = load_file("mercurial_comments", ".hgignore", "syntax: glob\nissue\\#12   # the bug's files\n  # only a comment\nre:a\\#b\n");
+        assert!(ignored_in(&loaded, "issue#12", false));
+        assert!(!ignored_in(&loaded, "issue", false));
+        assert!(ignored_in(&loaded, "xa#by", false));
+        assert!(!ignored_in(&loaded, "a", false));
+        assert_eq!(loaded.1.rules.len(), 2);
+    }
+
+    #[test]
+    fn mercurial_regexes_match_anywhere_unless_anchored () {
+        let loaded = load_file("mercurial_regexes", ".hgignore", "foo\n^bar\nbaz$\n");
+        assert!(ignored_in(&loaded, "foo", false));
+        assert!(ignored_in(&loaded, "a/xfooy/b", false));
+        assert!(ignored_in(&loaded, "bar/x", false));
+        assert!(ignored_in(&loaded, "barx", false));
+        assert!(!ignored_in(&loaded, "a/bar", false));
+        assert!(ignored_in(&loaded, "a/baz", false));
+        assert!(!ignored_in(&loaded, "baz/a", false));
+    }
+
+    #[test]
+    fn docker_rules () {
+        let loaded = load_file("docker_rules", ".dockerignore",
+                               "# comment\n*.md\n!README.md\nbuild/\n**/*.tmp\n./secrets\n/logs\n  node_modules  \n");
+        // Patterns are relative to the directory of the .dockerignore, not names at any depth.
+        assert!(ignored_in(&loaded, "CHANGES.md", false));
+        assert!(!ignored_in(&loaded, "docs/CHANGES.md", false));
+        assert!(!ignored_in(&loaded, "README.md", false));
+        // A trailing slash doesn't limit a pattern to directories.
+        assert!(ignored_in(&loaded, "build", true));
+        assert!(ignored_in(&loaded, "build", false));
+        assert!(!ignored_in(&loaded, "src/build", true));
+        assert!(ignored_in(&loaded, "a.tmp", false));
+        assert!(ignored_in(&loaded, "a/b/c.tmp", false));
+        assert!(ignored_in(&loaded, "secrets", false));
+        assert!(ignored_in(&loaded, "logs", true));
+        assert!(!ignored_in(&loaded, "app/logs", true));
+        assert!(ignored_in(&loaded, "node_modules", true));
+        assert!(!ignored_in(&loaded, "# comment", false));
+    }
+}
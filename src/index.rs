@@ -0,0 +1,567 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path;
+use std::process;
+use std::sync::mpsc;
+use std::thread;
+use std::time::UNIX_EPOCH;
+use find;
+use ignore;
+use stats;
+use log::*;
+
+// Index files start with these bytes and then the version of their format, which is increased
+// whenever the format changes so older indexes are rebuilt rather than misread.
+const MAGIC: &[u8; 4] = b"FEIX";
+const VERSION: u64 = 1;
+
+// Flags stored with each entry.
+const KIND_FILE: u8 = 0;
+const KIND_DIR: u8 = 1;
+const KIND_SYMLINK: u8 = 2;
+const KIND_OTHER: u8 = 3;
+const KIND_MASK: u8 = 3;
+const FLAG_IGNORED: u8 = 4;
+
+pub enum IndexError {
+    Io(io::Error),
+    Invalid(String),
+}
+
+// When a file was last modified, or `None` if it doesn't exist.
+pub type Stamp = Option<(u64, u32)>;
+
+pub fn stamp (path: &path::Path) -> Stamp {
+    //! Links aren't followed, as they aren't when walking directories.
+    stats::add(&stats::STAT_CALLS, 1);
+    let modified = fs::symlink_metadata(path).and_then(|m| m.modified()).ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+pub struct Entry {
+    // The path relative to the search root.
+    path: Vec<u8>,
+    flags: u8,
+    modified: Stamp,
+    // Ignored paths are kept so the index records what the ignore rules decided, but ignored
+    // directories aren't walked so nothing in them is.
+    pub ignored: bool,
+}
+
+impl Entry {
+
+    pub fn path (&self) -> &path::Path {
+        path::Path::new(OsStr::from_bytes(&self.path))
+    }
+
+    fn is_dir (&self) -> bool {
+        self.flags & KIND_MASK == KIND_DIR
+    }
+
+    fn is_ignore_file (&self, options: &super::Options) -> bool {
+        self.path().file_name().is_some_and(|name| ignore::is_ignore_file(name, options))
+    }
+}
+
+// The paths under a search root, saved by `fe index build` so searches don't have to walk
+// directories. It's up to date as long as none of the directories in it, the ignore files in them
+// or the ignore files outside of the root have been modified since it was built.
+pub struct Index {
+    // The canonical path of the search root.
+    root: path::PathBuf,
+    // The search root and ignore files outside of it, with when they were modified.
+    sources: Vec<(path::PathBuf, Stamp)>,
+    // Sorted by path.
+    pub entries: Vec<Entry>,
+}
+
+impl Index {
+
+    pub fn build (options: &super::Options) -> Result<(Index, path::PathBuf), IndexError> {
+        //! Walks `options.search_dir` and writes its index. Returns the index and where it was written.
+        // Searches only read indexes when they ignore what's ignored by default, so that's what
+        // gets marked as ignored whatever the options.
+        let options = &super::Options {
+            no_ignore: false,
+            no_ignore_vcs: false,
+            ignore_files: ignore::IGNORE_FILES.iter().map(|name| name.to_string()).collect(),
+            ignore_file_case_insensitive: false,
+            ..options.clone()
+        };
+        let search_dir = path::Path::new(&options.search_dir);
+        let root = search_dir.canonicalize().map_err(IndexError::Io)?;
+        let (sender, receiver) = mpsc::channel();
+        let mut entries = thread::scope(|scope| {
+            scope.spawn(move || find::walk_entries(options, sender));
+            receiver.iter().filter_map(|(path, ignored)| Index::entry(search_dir, &path, ignored)).collect::<Vec<Entry>>()
+        });
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut sources = vec![(root.clone(), stamp(&root))];
+        for (ignore_file, _) in ignore::RuleSet::root_ignore_files(search_dir, options) {
+            let modified = stamp(&ignore_file);
+            sources.push((ignore_file, modified));
+        }
+        let index = Index {
+            root,
+            sources,
+            entries,
+        };
+
+        let index_path = cache_file(&index.root, "index");
+        write_cache_file(&index_path, &index.encode()).map_err(IndexError::Io)?;
+        Ok((index, index_path))
+    }
+
+    fn entry (search_dir: &path::Path, path: &path::Path, ignored: bool) -> Option<Entry> {
+        let relative = path.strip_prefix(search_dir).ok()?;
+        stats::add(&stats::STAT_CALLS, 1);
+        let metadata = fs::symlink_metadata(path).ok()?;
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            KIND_SYMLINK
+        } else if file_type.is_dir() {
+            KIND_DIR
+        } else if file_type.is_file() {
+            KIND_FILE
+        } else {
+            KIND_OTHER
+        };
+        let modified = metadata.modified().ok()
+            .map(|m| m.duration_since(UNIX_EPOCH).unwrap_or_default())
+            .map(|d| (d.as_secs(), d.subsec_nanos()));
+        Some(Entry {
+            path: relative.as_os_str().as_bytes().to_vec(),
+            flags: kind | if ignored { FLAG_IGNORED } else { 0 },
+            modified,
+            ignored,
+        })
+    }
+
+    pub fn load (options: &super::Options) -> Option<Index> {
+        //! Reads the index for `options.search_dir`, if it has one. Indexes that can't be read or
+        //! aren't valid are logged and skipped.
+        let root = path::Path::new(&options.search_dir).canonicalize().ok()?;
+        let index_path = cache_file(&root, "index");
+        let bytes = match fs::read(&index_path) {
+            Ok(b) => b,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    v(format!("Failed to read the index {:?}: {}", index_path, e), options);
+                }
+                return None;
+            },
+        };
+        match Index::decode(&bytes) {
+            Ok(index) if index.root == root => {
+                v(format!("Found an index of {} paths at {:?}", index.entries.len(), index_path), options);
+                Some(index)
+            },
+            Ok(_) => {
+                v(format!("The index {:?} is for another directory.", index_path), options);
+                None
+            },
+            Err(IndexError::Invalid(e)) => {
+                v(format!("The index {:?} isn't valid, rebuild it with fe index build: {}", index_path, e), options);
+                None
+            },
+            Err(IndexError::Io(e)) => {
+                v(format!("Failed to read the index {:?}: {}", index_path, e), options);
+                None
+            },
+        }
+    }
+
+    pub fn is_fresh (&self, options: &super::Options) -> bool {
+        //! Checks nothing that decides what's in the index has been modified since it was built.
+        //! Directories are modified when entries are added to or removed from them, and the ignore
+        //! files decide which paths are ignored.
+        for &(ref source, modified) in &self.sources {
+            if stamp(source) != modified {
+                v(format!("{:?} changed since the index was built.", source), options);
+                return false;
+            }
+        }
+        let search_dir = path::Path::new(&options.search_dir);
+        for entry in &self.entries {
+            let watched = (entry.is_dir() && !entry.ignored) || entry.is_ignore_file(options);
+            if watched && stamp(&search_dir.join(entry.path())) != entry.modified {
+                v(format!("{:?} changed since the index was built.", entry.path()), options);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn encode (&self) -> Vec<u8> {
+        //! The format is, with numbers as LEB128 varints and byte strings prefixed by their length:
+        //!
+        //!     * `FEIX` and the format version.
+        //!     * The canonical search root.
+        //!     * The number of sources, then each source's path and modification stamp.
+        //!     * The number of entries, then for each entry, in path order, how many leading bytes
+        //!         its path shares with the previous entry's, the rest of its path, its flags and
+        //!         its modification stamp.
+        //!     * An FNV-1a hash of everything before it, as 8 little endian bytes.
+        //!
+        //! A modification stamp is a 0 byte if the file didn't exist, or a 1 byte then the seconds
+        //! and nanoseconds since the epoch.
+        let mut bytes = start_file(MAGIC, VERSION);
+        write_bytes(&mut bytes, self.root.as_os_str().as_bytes());
+        write_varint(&mut bytes, self.sources.len() as u64);
+        for &(ref source, modified) in &self.sources {
+            write_bytes(&mut bytes, source.as_os_str().as_bytes());
+            write_stamp(&mut bytes, modified);
+        }
+        write_varint(&mut bytes, self.entries.len() as u64);
+        let mut previous: &[u8] = &[];
+        for entry in &self.entries {
+            let shared = previous.iter().zip(&entry.path).take_while(|&(a, b)| a == b).count();
+            write_varint(&mut bytes, shared as u64);
+            write_bytes(&mut bytes, &entry.path[shared..]);
+            bytes.push(entry.flags);
+            write_stamp(&mut bytes, entry.modified);
+            previous = &entry.path;
+        }
+        end_file(bytes)
+    }
+
+    fn decode (bytes: &[u8]) -> Result<Index, IndexError> {
+        let mut reader = Reader::open(bytes, MAGIC, VERSION)?;
+        let root = path::PathBuf::from(OsStr::from_bytes(reader.bytes()?));
+        let source_count = reader.count()?;
+        let mut sources = Vec::with_capacity(source_count);
+        for _ in 0..source_count {
+            let source = path::PathBuf::from(OsStr::from_bytes(reader.bytes()?));
+            sources.push((source, reader.stamp()?));
+        }
+        let entry_count = reader.count()?;
+        let mut entries: Vec<Entry> = Vec::with_capacity(entry_count);
+        for _ in 0..entry_count {
+            let shared = reader.varint()? as usize;
+            let mut path = match entries.last() {
+                Some(previous) if shared <= previous.path.len() => previous.path[..shared].to_vec(),
+                None if shared == 0 => vec![],
+                _ => return Err(IndexError::Invalid(String::from("an entry shares more of its path than there is"))),
+            };
+            path.extend_from_slice(reader.bytes()?);
+            let flags = reader.byte()?;
+            let modified = reader.stamp()?;
+            entries.push(Entry {
+                path,
+                flags,
+                modified,
+                ignored: flags & FLAG_IGNORED != 0,
+            });
+        }
+        reader.finish()?;
+        Ok(Index {
+            root,
+            sources,
+            entries,
+        })
+    }
+}
+
+pub fn cache_file (root: &path::Path, extension: &str) -> path::PathBuf {
+    //! Where to keep a file about `root`: in `$XDG_CACHE_HOME/fe`, named after a hash of the root,
+    //! or in the root itself if there's no cache directory.
+    let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(c) if !c.is_empty() => Some(path::PathBuf::from(c)),
+        _ => env::var_os("HOME").map(|h| path::PathBuf::from(h).join(".cache")),
+    };
+    match cache_dir {
+        Some(c) => c.join("fe").join(format!("{:016x}.{}", fnv1a(root.as_os_str().as_bytes()), extension)),
+        None => root.join(format!(".fe-{}", extension)),
+    }
+}
+
+pub fn write_cache_file (file: &path::Path, bytes: &[u8]) -> io::Result<()> {
+    //! Writes next to where the file goes and renames it so it's never read half written. The
+    //! temporary file is named after the whole file name and the process, so processes writing the
+    //! same or another file about the same root don't write to each other's.
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temporary_path = file.as_os_str().to_os_string();
+    temporary_path.push(format!(".{}.tmp", process::id()));
+    let temporary_path = path::PathBuf::from(temporary_path);
+    fs::write(&temporary_path, bytes)?;
+    fs::rename(&temporary_path, file)
+}
+
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+
+    pub fn open (bytes: &'a [u8], magic: &[u8], version: u64) -> Result<Reader<'a>, IndexError> {
+        //! Checks a file written with `start_file` and `end_file` and returns a reader for what's
+        //! after its header.
+        if bytes.len() < magic.len() + 8 || &bytes[..magic.len()] != magic {
+            return Err(IndexError::Invalid(String::from("it's not a file fe wrote")));
+        }
+        let (body, hash) = bytes.split_at(bytes.len() - 8);
+        let mut reader = Reader {
+            bytes: body,
+            position: magic.len(),
+        };
+        let found_version = reader.varint()?;
+        if found_version != version {
+            return Err(IndexError::Invalid(format!("version {} isn't supported, only version {} is", found_version, version)));
+        }
+        let mut hash_bytes = [0; 8];
+        hash_bytes.copy_from_slice(hash);
+        if u64::from_le_bytes(hash_bytes) != fnv1a(body) {
+            return Err(IndexError::Invalid(String::from("its checksum doesn't match, it may be corrupt")));
+        }
+        Ok(reader)
+    }
+
+    pub fn finish (&self) -> Result<(), IndexError> {
+        if self.position != self.bytes.len() {
+            return Err(IndexError::Invalid(String::from("there's data after the end")));
+        }
+        Ok(())
+    }
+
+    pub fn byte (&mut self) -> Result<u8, IndexError> {
+        let b = *self.bytes.get(self.position).ok_or_else(|| IndexError::Invalid(String::from("it ends early")))?;
+        self.position += 1;
+        Ok(b)
+    }
+
+    pub fn varint (&mut self) -> Result<u64, IndexError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= u64::from(b & 0x7f) << shift;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(IndexError::Invalid(String::from("a number is too long")))
+    }
+
+    pub fn count (&mut self) -> Result<usize, IndexError> {
+        //! Reads a number of things that follow, which can't be more than the bytes left.
+        let count = self.varint()? as usize;
+        if count > self.bytes.len() - self.position {
+            return Err(IndexError::Invalid(String::from("it has more entries than it has room for")));
+        }
+        Ok(count)
+    }
+
+    pub fn bytes (&mut self) -> Result<&'a [u8], IndexError> {
+        let len = self.varint()? as usize;
+        if len > self.bytes.len() - self.position {
+            return Err(IndexError::Invalid(String::from("it ends early")));
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    pub fn stamp (&mut self) -> Result<Stamp, IndexError> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some((self.varint()?, self.varint()? as u32))),
+            b => Err(IndexError::Invalid(format!("unexpected byte {} in a modification time", b))),
+        }
+    }
+}
+
+pub fn start_file (magic: &[u8], version: u64) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    write_varint(&mut bytes, version);
+    bytes
+}
+
+pub fn end_file (mut bytes: Vec<u8>) -> Vec<u8> {
+    //! Adds the checksum `Reader::open` checks.
+    let hash = fnv1a(&bytes);
+    bytes.extend_from_slice(&hash.to_le_bytes());
+    bytes
+}
+
+pub fn write_varint (bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let b = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(b);
+            return;
+        }
+        bytes.push(b | 0x80);
+    }
+}
+
+pub fn write_bytes (bytes: &mut Vec<u8>, value: &[u8]) {
+    write_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value);
+}
+
+pub fn write_stamp (bytes: &mut Vec<u8>, modified: Stamp) {
+    match modified {
+        Some((seconds, nanoseconds)) => {
+            bytes.push(1);
+            write_varint(bytes, seconds);
+            write_varint(bytes, u64::from(nanoseconds));
+        },
+        None => bytes.push(0),
+    }
+}
+
+fn fnv1a (bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn build (options: &super::Options) -> bool {
+    //! Builds the index for `fe index build`, returns false if it couldn't be written.
+    match Index::build(options) {
+        Ok((index, index_path)) => {
+            println!("Indexed {} paths in {}", index.entries.iter().filter(|e| !e.ignored).count(), index_path.display());
+            stats::print(options);
+            true
+        },
+        Err(IndexError::Io(e)) => {
+            eprintln!("fe: failed to build the index of {}: {}", options.search_dir, e);
+            false
+        },
+        Err(IndexError::Invalid(e)) => {
+            eprintln!("fe: failed to build the index of {}: {}", options.search_dir, e);
+            false
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry (path: &[u8], kind: u8, ignored: bool, modified: Stamp) -> Entry {
+        Entry {
+            path: path.to_vec(),
+            flags: kind | if ignored { FLAG_IGNORED } else { 0 },
+            modified,
+            ignored,
+        }
+    }
+
+    fn index () -> Index {
+        Index {
+            root: path::PathBuf::from("/home/me/project"),
+            sources: vec![
+                (path::PathBuf::from("/home/me/project"), Some((1_700_000_000, 123_456_789))),
+                (path::PathBuf::from("/home/me/.config/git/ignore"), None),
+            ],
+            entries: vec![
+                entry(b"caf\xe9.txt", KIND_FILE, false, Some((1, 2))),
+                entry(b"src", KIND_DIR, false, Some((1_700_000_000, 0))),
+                entry(b"src/main.rs", KIND_FILE, false, Some((1_700_000_001, 999_999_999))),
+                entry(b"src/main.rs.orig", KIND_SYMLINK, false, None),
+                entry(b"target", KIND_DIR, true, Some((u64::MAX, 0))),
+            ],
+        }
+    }
+
+    fn invalid (result: Result<Index, IndexError>) -> String {
+        match result {
+            Err(IndexError::Invalid(e)) => e,
+            Err(IndexError::Io(e)) => panic!("expected an invalid index, got {}", e),
+            Ok(_) => panic!("expected an invalid index, it was read"),
+        }
+    }
+
+    #[test]
+    fn round_trip () {
+        let original = index();
+        let decoded = match Index::decode(&original.encode()) {
+            Ok(i) => i,
+            Err(_) => panic!("the encoded index couldn't be read"),
+        };
+        assert_eq!(decoded.root, original.root);
+        assert_eq!(decoded.sources, original.sources);
+        assert_eq!(decoded.entries.len(), original.entries.len());
+        for (d, o) in decoded.entries.iter().zip(&original.entries) {
+            assert_eq!(d.path, o.path);
+            assert_eq!(d.flags, o.flags);
+            assert_eq!(d.modified, o.modified);
+            assert_eq!(d.ignored, o.ignored);
+        }
+    }
+
+    #[test]
+    fn round_trip_empty () {
+        let original = Index {
+            root: path::PathBuf::from("/"),
+            sources: vec![],
+            entries: vec![],
+        };
+        let decoded = match Index::decode(&original.encode()) {
+            Ok(i) => i,
+            Err(_) => panic!("the encoded index couldn't be read"),
+        };
+        assert_eq!(decoded.root, original.root);
+        assert!(decoded.sources.is_empty());
+        assert!(decoded.entries.is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_magic () {
+        let mut bytes = index().encode();
+        bytes[0] = b'X';
+        assert!(invalid(Index::decode(&bytes)).contains("not a file fe wrote"));
+        assert!(invalid(Index::decode(b"")).contains("not a file fe wrote"));
+    }
+
+    #[test]
+    fn rejects_wrong_version () {
+        let mut bytes = index().encode();
+        bytes[MAGIC.len()] = VERSION as u8 + 1;
+        assert!(invalid(Index::decode(&bytes)).contains("version"));
+    }
+
+    #[test]
+    fn rejects_bad_checksum () {
+        let mut bytes = index().encode();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        assert!(invalid(Index::decode(&bytes)).contains("checksum"));
+        let last = bytes.len() - 1;
+        bytes[middle] ^= 0xff;
+        bytes[last] ^= 0xff;
+        assert!(invalid(Index::decode(&bytes)).contains("checksum"));
+    }
+
+    #[test]
+    fn rejects_truncated_files () {
+        let bytes = index().encode();
+        for length in 0..bytes.len() {
+            invalid(Index::decode(&bytes[..length]));
+        }
+        // A body that ends early is caught even with a checksum that matches it.
+        let body = &bytes[..bytes.len() - 8];
+        let truncated = end_file(body[..body.len() - 3].to_vec());
+        assert!(invalid(Index::decode(&truncated)).contains("ends early"));
+    }
+
+    #[test]
+    fn rejects_trailing_data () {
+        let bytes = index().encode();
+        let mut body = bytes[..bytes.len() - 8].to_vec();
+        body.push(0);
+        assert!(invalid(Index::decode(&end_file(body))).contains("after the end"));
+    }
+}
@@ -0,0 +1,559 @@
+use libc;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::io::{Read, Write};
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::path;
+use std::str;
+use std::sync::mpsc;
+use std::thread;
+use display;
+use find;
+use log::*;
+
+// How long to wait for a key press before checking for newly found paths.
+const POLL_TIMEOUT_MS: i32 = 50;
+
+// Lines at the top of the screen used by the prompt and the match count.
+const HEADER_LINES: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Char(char),
+    Up,
+    Down,
+    Tab,
+    Enter,
+    Backspace,
+    Escape,
+    CtrlC,
+    CtrlN,
+    CtrlP,
+    CtrlU,
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Continue,
+    Accept,
+    Cancel,
+}
+
+fn parse_keys (bytes: &[u8]) -> Vec<Key> {
+    //! Decodes keys read from a terminal in raw mode. Escape sequences other than the up and down
+    //! arrows are skipped.
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let key = match bytes[i] {
+            0x1b => {
+                if i + 1 >= bytes.len() || (bytes[i + 1] != b'[' && bytes[i + 1] != b'O') {
+                    i += 1;
+                    keys.push(Key::Escape);
+                    continue;
+                }
+                // Parameters come before a final byte in the range `@` to `~`.
+                let end = bytes[i + 2..].iter().position(|b| (0x40..=0x7e).contains(b)).map(|p| i + 2 + p);
+                let end = match end {
+                    Some(e) => e,
+                    None => break,
+                };
+                let key = match bytes[end] {
+                    b'A' => Some(Key::Up),
+                    b'B' => Some(Key::Down),
+                    _ => None,
+                };
+                // Past the final byte, which the arrows skip below.
+                i = end;
+                match key {
+                    Some(k) => k,
+                    None => {
+                        i += 1;
+                        continue;
+                    },
+                }
+            },
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x03 => Key::CtrlC,
+            0x0e => Key::CtrlN,
+            0x10 => Key::CtrlP,
+            0x15 => Key::CtrlU,
+            b if b < 0x20 => {
+                i += 1;
+                continue;
+            },
+            _ => {
+                let len = match str::from_utf8(&bytes[i..]) {
+                    Ok(_) => bytes.len() - i,
+                    Err(e) => e.valid_up_to(),
+                };
+                match str::from_utf8(&bytes[i..i + len]).ok().and_then(|s| s.chars().next()) {
+                    Some(c) => {
+                        i += c.len_utf8();
+                        keys.push(Key::Char(c));
+                    },
+                    // Not UTF-8, or cut off at the end of what was read.
+                    None => i += 1,
+                }
+                continue;
+            },
+        };
+        i += 1;
+        keys.push(key);
+    }
+    keys
+}
+
+fn parse_script (script: &str) -> Result<Vec<Key>, String> {
+    //! Parses keys given with `--keys`. Characters type themselves and other keys are written in
+    //! angle brackets: `<up>`, `<down>`, `<tab>`, `<enter>`, `<esc>`, `<bs>`, `<c-c>`, `<c-n>`,
+    //! `<c-p>` and `<c-u>`. `<lt>` types a `<`.
+    let mut keys = vec![];
+    let mut rest = script;
+    while let Some(c) = rest.chars().next() {
+        if c != '<' {
+            keys.push(Key::Char(c));
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        let end = rest.find('>').ok_or_else(|| format!("unterminated key in {}", rest))?;
+        let key = match &rest[1..end] {
+            "up" => Key::Up,
+            "down" => Key::Down,
+            "tab" => Key::Tab,
+            "enter" => Key::Enter,
+            "esc" => Key::Escape,
+            "bs" => Key::Backspace,
+            "c-c" => Key::CtrlC,
+            "c-n" => Key::CtrlN,
+            "c-p" => Key::CtrlP,
+            "c-u" => Key::CtrlU,
+            "lt" => Key::Char('<'),
+            name => return Err(format!("unknown key <{}>", name)),
+        };
+        keys.push(key);
+        rest = &rest[end + 1..];
+    }
+    Ok(keys)
+}
+
+struct Picker<'a> {
+    // Every path found so far, in the order they were found.
+    paths: Vec<path::PathBuf>,
+    query: String,
+    // Indices into `paths` of the paths matching `query` and how they matched, best first.
+    matches: Vec<(usize, find::Match)>,
+    // Index into `matches` of the highlighted row.
+    selected: usize,
+    // The first row of `matches` shown on screen.
+    scroll: usize,
+    // Indices into `paths` picked with tab.
+    marked: BTreeSet<usize>,
+    options: &'a super::Options,
+}
+
+impl<'a> Picker<'a> {
+
+    fn new (query: &str, options: &'a super::Options) -> Picker<'a> {
+        Picker {
+            paths: vec![],
+            query: String::from(query),
+            matches: vec![],
+            selected: 0,
+            scroll: 0,
+            marked: BTreeSet::new(),
+            options,
+        }
+    }
+
+    fn add_paths (&mut self, paths: Vec<path::PathBuf>) {
+        let search = find::SearchContext::fuzzy(&self.query, self.options);
+        for path in paths {
+            if let Some(matched) = search.match_path(&path) {
+                self.matches.push((self.paths.len(), matched));
+            }
+            self.paths.push(path);
+        }
+        self.sort();
+    }
+
+    fn set_query (&mut self, query: String) {
+        //! Re-filters matches for `query`. Anything matching a longer query also matches the
+        //! shorter one it was typed from, so when typing only the current matches are searched.
+        let search = find::SearchContext::fuzzy(&query, self.options);
+        let candidates: Vec<usize> = if query.starts_with(&self.query) {
+            self.matches.iter().map(|&(i, _)| i).collect()
+        } else {
+            (0..self.paths.len()).collect()
+        };
+        let paths = &self.paths;
+        self.matches = candidates.into_iter()
+            .filter_map(|i| search.match_path(&paths[i]).map(|m| (i, m)))
+            .collect();
+        self.query = query;
+        self.selected = 0;
+        self.scroll = 0;
+        self.sort();
+    }
+
+    fn sort (&mut self) {
+        // Best scores first, ties are broken by path like the search results.
+        let paths = &self.paths;
+        self.matches.sort_by(|a, b| b.1.score.cmp(&a.1.score).then_with(|| paths[a.0].cmp(&paths[b.0])));
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    fn handle (&mut self, key: Key) -> Action {
+        match key {
+            Key::Char(c) => {
+                let mut query = self.query.clone();
+                query.push(c);
+                self.set_query(query);
+            },
+            Key::Backspace => {
+                let mut query = self.query.clone();
+                if query.pop().is_some() {
+                    self.set_query(query);
+                }
+            },
+            Key::CtrlU => self.set_query(String::new()),
+            Key::Up | Key::CtrlP => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::CtrlN => self.move_down(),
+            Key::Tab => {
+                if let Some(&(i, _)) = self.matches.get(self.selected) {
+                    if !self.marked.remove(&i) {
+                        self.marked.insert(i);
+                    }
+                }
+                self.move_down();
+            },
+            Key::Enter => return Action::Accept,
+            Key::Escape | Key::CtrlC => return Action::Cancel,
+        }
+        Action::Continue
+    }
+
+    fn move_down (&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    fn chosen (&self) -> Vec<&path::PathBuf> {
+        //! The marked paths, or the highlighted one if none are marked.
+        if !self.marked.is_empty() {
+            return self.marked.iter().map(|&i| &self.paths[i]).collect();
+        }
+        self.matches.get(self.selected).map(|&(i, _)| &self.paths[i]).into_iter().collect()
+    }
+
+    fn render (&mut self, rows: usize, columns: usize, walking: bool) -> String {
+        //! Draws the prompt, the match count and as many matches as fit on screen.
+        let rows = rows.saturating_sub(HEADER_LINES).max(1);
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        let mut screen = String::from("\x1b[H");
+        screen.push_str(&format!("> {}\x1b[K\r\n", self.query));
+        screen.push_str(&format!("  {}/{}{}{}\x1b[K\r\n",
+            self.matches.len(),
+            self.paths.len(),
+            if self.marked.is_empty() { String::new() } else { format!(" ({} marked)", self.marked.len()) },
+            if walking { " ..." } else { "" }));
+        for (row, &(i, ref matched)) in self.matches.iter().enumerate().skip(self.scroll).take(rows) {
+            let cursor = if row == self.selected { "\x1b[1m>\x1b[0m" } else { " " };
+            let mark = if self.marked.contains(&i) { "*" } else { " " };
+            let path = display::ansi_path(&self.paths[i], &matched.positions, columns.saturating_sub(3), self.options);
+            screen.push_str(&format!("{}{} {}\x1b[K\r\n", cursor, mark, path));
+        }
+        // Clear anything left below from a longer list.
+        screen.push_str("\x1b[J");
+        screen
+    }
+}
+
+// The controlling terminal in raw mode on the alternate screen. Dropping it puts the terminal back
+// the way it was.
+struct Terminal {
+    tty: fs::File,
+    original: libc::termios,
+}
+
+impl Terminal {
+
+    fn open () -> io::Result<Terminal> {
+        //! Opens `/dev/tty` rather than using stdin and stdout so the picker still works when
+        //! they're redirected, like in `vim $(fe --interactive)`.
+        let tty = fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let fd = tty.as_raw_fd();
+        let mut original: libc::termios = unsafe { mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = original;
+        raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        raw.c_cflag |= libc::CS8;
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut terminal = Terminal {
+            tty,
+            original,
+        };
+        // Switch to the alternate screen and hide the cursor.
+        terminal.write("\x1b[?1049h\x1b[?25l")?;
+        Ok(terminal)
+    }
+
+    fn size (&self) -> (usize, usize) {
+        //! Returns the number of rows and columns, or a guess if the terminal doesn't know.
+        let mut size: libc::winsize = unsafe { mem::zeroed() };
+        let ok = unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } == 0;
+        if !ok || size.ws_row == 0 || size.ws_col == 0 {
+            return (24, 80);
+        }
+        (size.ws_row as usize, size.ws_col as usize)
+    }
+
+    fn read_keys (&mut self) -> io::Result<Vec<Key>> {
+        //! Waits up to `POLL_TIMEOUT_MS` for key presses and returns any there were.
+        let mut poll = libc::pollfd {
+            fd: self.tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ready = unsafe { libc::poll(&mut poll, 1, POLL_TIMEOUT_MS) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            // Resizing the terminal interrupts poll.
+            if e.kind() == io::ErrorKind::Interrupted {
+                return Ok(vec![]);
+            }
+            return Err(e);
+        }
+        if ready == 0 {
+            return Ok(vec![]);
+        }
+        let mut buffer = [0; 256];
+        let read = self.tty.read(&mut buffer)?;
+        Ok(parse_keys(&buffer[..read]))
+    }
+
+    fn write (&mut self, s: &str) -> io::Result<()> {
+        self.tty.write_all(s.as_bytes())?;
+        self.tty.flush()
+    }
+}
+
+impl Drop for Terminal {
+
+    fn drop (&mut self) {
+        let _ = self.write("\x1b[?25h\x1b[?1049l");
+        unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSAFLUSH, &self.original) };
+    }
+}
+
+pub fn run (query: &str, options: &super::Options) -> bool {
+    //! Lets the user pick paths under `options.search_dir`, filtering them with a fuzzy search as
+    //! they type, while they are still being found in the background. The chosen paths are printed
+    //! when done. Returns false if the picker was cancelled or nothing was chosen.
+    let keys = match options.keys {
+        Some(ref script) => match parse_script(script) {
+            Ok(keys) => Some(keys),
+            Err(e) => {
+                eprintln!("fe: --keys: {}", e);
+                return false;
+            },
+        },
+        None => None,
+    };
+
+    let mut picker = Picker::new(query, options);
+    let (sender, receiver) = mpsc::channel();
+    let action = thread::scope(|scope| {
+        scope.spawn(move || find::walk_paths(options, sender));
+        // The receiver is dropped when the picker is done, which stops the walk.
+        match keys {
+            Some(keys) => run_script(&mut picker, receiver, keys),
+            None => run_terminal(&mut picker, receiver),
+        }
+    });
+
+    if action != Action::Accept {
+        return false;
+    }
+    let chosen = picker.chosen();
+    for path in &chosen {
+        display::print_chosen(path, options);
+    }
+    !chosen.is_empty()
+}
+
+fn run_script (picker: &mut Picker, receiver: mpsc::Receiver<path::PathBuf>, keys: Vec<Key>) -> Action {
+    //! Presses `keys` once every path has been found, so results don't depend on timing. Scripts
+    //! that end without accepting are cancelled.
+    picker.add_paths(receiver.iter().collect());
+    for key in keys {
+        vv(format!("Pressing {:?}", key), picker.options);
+        match picker.handle(key) {
+            Action::Continue => (),
+            action => return action,
+        }
+    }
+    Action::Cancel
+}
+
+fn run_terminal (picker: &mut Picker, receiver: mpsc::Receiver<path::PathBuf>) -> Action {
+    let mut terminal = match Terminal::open() {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("fe: can't use the terminal for --interactive: {}", e);
+            return Action::Cancel;
+        },
+    };
+    let mut walking = true;
+    let mut changed = true;
+    let mut size = (0, 0);
+    loop {
+        if walking {
+            let mut found = vec![];
+            loop {
+                match receiver.try_recv() {
+                    Ok(path) => found.push(path),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        walking = false;
+                        changed = true;
+                        break;
+                    },
+                }
+            }
+            if !found.is_empty() {
+                picker.add_paths(found);
+                changed = true;
+            }
+        }
+
+        if changed || terminal.size() != size {
+            size = terminal.size();
+            let screen = picker.render(size.0, size.1, walking);
+            if terminal.write(&screen).is_err() {
+                return Action::Cancel;
+            }
+            changed = false;
+        }
+
+        let keys = match terminal.read_keys() {
+            Ok(k) => k,
+            Err(e) => {
+                v(format!("Failed to read from the terminal: {}", e), picker.options);
+                return Action::Cancel;
+            },
+        };
+        for key in keys {
+            changed = true;
+            match picker.handle(key) {
+                Action::Continue => (),
+                action => return action,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATHS: [&str; 4] = ["README.md", "src/find.rs", "src/interactive.rs", "src/main.rs"];
+
+    fn pick (query: &str, script: &str, options: &super::super::Options) -> (Action, Vec<String>) {
+        //! Runs the picker over `PATHS` like `--keys script` does.
+        let mut picker = Picker::new(query, options);
+        let (sender, receiver) = mpsc::channel();
+        for path in &PATHS {
+            sender.send(path::PathBuf::from(path)).unwrap();
+        }
+        drop(sender);
+        let keys = parse_script(script).unwrap();
+        let action = run_script(&mut picker, receiver, keys);
+        let chosen = picker.chosen().iter().map(|p| p.display().to_string()).collect();
+        (action, chosen)
+    }
+
+    #[test]
+    fn terminal_keys () {
+        assert_eq!(parse_keys(b"ab\r\n\t\x7f\x08"),
+                   vec![Key::Char('a'), Key::Char('b'), Key::Enter, Key::Enter, Key::Tab, Key::Backspace, Key::Backspace]);
+        assert_eq!(parse_keys(b"\x03\x0e\x10\x15"), vec![Key::CtrlC, Key::CtrlN, Key::CtrlP, Key::CtrlU]);
+        assert_eq!(parse_keys(b"\x1b[A\x1bOB"), vec![Key::Up, Key::Down]);
+        // Other escape sequences and control characters are skipped, a lone escape is a key.
+        assert_eq!(parse_keys(b"\x1b[1;5Cx\x01\x1b"), vec![Key::Char('x'), Key::Escape]);
+        assert_eq!(parse_keys(b"\x1bx"), vec![Key::Escape, Key::Char('x')]);
+        assert_eq!(parse_keys("é".as_bytes()), vec![Key::Char('é')]);
+        assert_eq!(parse_keys(b"\xc3"), vec![]);
+        assert_eq!(parse_keys(b"\xffa"), vec![Key::Char('a')]);
+        assert_eq!(parse_keys(b"\x1b["), vec![]);
+    }
+
+    #[test]
+    fn scripts () {
+        assert_eq!(parse_script("a<up><down><tab><enter><esc><bs><c-c><c-n><c-p><c-u><lt>"), Ok(vec![
+            Key::Char('a'), Key::Up, Key::Down, Key::Tab, Key::Enter, Key::Escape, Key::Backspace,
+            Key::CtrlC, Key::CtrlN, Key::CtrlP, Key::CtrlU, Key::Char('<'),
+        ]));
+        assert_eq!(parse_script(""), Ok(vec![]));
+        assert_eq!(parse_script("><é"), Err("unterminated key in <é".to_string()));
+        assert_eq!(parse_script("<f1>"), Err("unknown key <f1>".to_string()));
+    }
+
+    #[test]
+    fn typing_filters () {
+        let options = super::super::Options::for_tests();
+        assert_eq!(pick("", "find<enter>", &options), (Action::Accept, vec!["src/find.rs".to_string()]));
+        assert_eq!(pick("mai", "<enter>", &options), (Action::Accept, vec!["src/main.rs".to_string()]));
+        // Deleting characters brings back paths the longer query didn't match.
+        assert_eq!(pick("", "findx<bs><bs><bs><bs><bs>READ<enter>", &options), (Action::Accept, vec!["README.md".to_string()]));
+        assert_eq!(pick("find", "<c-u>main<enter>", &options), (Action::Accept, vec!["src/main.rs".to_string()]));
+    }
+
+    #[test]
+    fn moving_the_selection () {
+        let options = super::super::Options::for_tests();
+        assert_eq!(pick("", "<enter>", &options).1, vec!["README.md"]);
+        assert_eq!(pick("", "<down><down><enter>", &options).1, vec!["src/interactive.rs"]);
+        assert_eq!(pick("", "<c-n><c-n><c-p><enter>", &options).1, vec!["src/find.rs"]);
+        // The selection stops at either end.
+        assert_eq!(pick("", "<up><enter>", &options).1, vec!["README.md"]);
+        assert_eq!(pick("", "<down><down><down><down><down><enter>", &options).1, vec!["src/main.rs"]);
+        // Typing selects the best match again.
+        assert_eq!(pick("", "<down><down>src<enter>", &options).1, vec!["src/find.rs"]);
+    }
+
+    #[test]
+    fn marking () {
+        let options = super::super::Options::for_tests();
+        assert_eq!(pick("", "<tab><down><tab><enter>", &options).1, vec!["README.md", "src/interactive.rs"]);
+        // Tab again unmarks, and marks stay while the query changes.
+        assert_eq!(pick("", "<tab><up><tab>main<tab><enter>", &options).1, vec!["src/main.rs"]);
+        assert_eq!(pick("", "<tab>main<tab><enter>", &options).1, vec!["README.md", "src/main.rs"]);
+    }
+
+    #[test]
+    fn exiting () {
+        let options = super::super::Options::for_tests();
+        assert_eq!(pick("", "main<esc><enter>", &options).0, Action::Cancel);
+        assert_eq!(pick("", "<c-c>", &options).0, Action::Cancel);
+        // Scripts that end without accepting cancel.
+        assert_eq!(pick("", "main", &options).0, Action::Cancel);
+        // Accepting with nothing matching chooses nothing.
+        assert_eq!(pick("", "zzz<enter>", &options), (Action::Accept, vec![]));
+    }
+}
@@ -0,0 +1,196 @@
+use std::char;
+use std::iter;
+use std::str;
+
+// Just enough JSON for the daemon's protocol.
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    // Keys in the order they were written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+
+    pub fn get (&self, key: &str) -> Option<&Json> {
+        match *self {
+            Json::Object(ref members) => members.iter().find(|m| m.0 == key).map(|m| &m.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str (&self) -> Option<&str> {
+        match *self {
+            Json::String(ref s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool (&self) -> Option<bool> {
+        match *self {
+            Json::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64 (&self) -> Option<f64> {
+        match *self {
+            Json::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn as_array (&self) -> Option<&[Json]> {
+        match *self {
+            Json::Array(ref a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+pub fn string (s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// How deeply arrays and objects can be nested, so parsing can't run out of stack.
+const MAX_DEPTH: usize = 32;
+
+pub fn parse (s: &str) -> Result<Json, String> {
+    let mut chars = s.chars().peekable();
+    let value = parse_value(&mut chars, 0)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(value),
+        Some(c) => Err(format!("unexpected {:?} after the value", c)),
+    }
+}
+
+type Chars<'a> = iter::Peekable<str::Chars<'a>>;
+
+fn skip_whitespace (chars: &mut Chars) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect (chars: &mut Chars, word: &str) -> Result<(), String> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(format!("expected {}", word));
+        }
+    }
+    Ok(())
+}
+
+fn parse_value (chars: &mut Chars, depth: usize) -> Result<Json, String> {
+    skip_whitespace(chars);
+    let next = chars.peek().cloned();
+    if depth == MAX_DEPTH && (next == Some('{') || next == Some('[')) {
+        return Err(format!("arrays and objects are nested more than {} deep", MAX_DEPTH));
+    }
+    match next {
+        Some('{') => {
+            chars.next();
+            let mut members = vec![];
+            skip_whitespace(chars);
+            if chars.peek() == Some(&'}') {
+                chars.next();
+                return Ok(Json::Object(members));
+            }
+            loop {
+                skip_whitespace(chars);
+                let key = parse_string(chars)?;
+                skip_whitespace(chars);
+                expect(chars, ":")?;
+                members.push((key, parse_value(chars, depth + 1)?));
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some('}') => return Ok(Json::Object(members)),
+                    _ => return Err(String::from("expected , or } in an object")),
+                }
+            }
+        },
+        Some('[') => {
+            chars.next();
+            let mut values = vec![];
+            skip_whitespace(chars);
+            if chars.peek() == Some(&']') {
+                chars.next();
+                return Ok(Json::Array(values));
+            }
+            loop {
+                values.push(parse_value(chars, depth + 1)?);
+                skip_whitespace(chars);
+                match chars.next() {
+                    Some(',') => (),
+                    Some(']') => return Ok(Json::Array(values)),
+                    _ => return Err(String::from("expected , or ] in an array")),
+                }
+            }
+        },
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('t') => expect(chars, "true").map(|_| Json::Bool(true)),
+        Some('f') => expect(chars, "false").map(|_| Json::Bool(false)),
+        Some('n') => expect(chars, "null").map(|_| Json::Null),
+        Some(c) if c == '-' || c.is_ascii_digit() => {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            number.parse().map(Json::Number).map_err(|_| format!("{} isn't a number", number))
+        },
+        Some(c) => Err(format!("unexpected {:?}", c)),
+        None => Err(String::from("unexpected end")),
+    }
+}
+
+fn parse_string (chars: &mut Chars) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err(String::from("expected a string"));
+    }
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('r') => s.push('\r'),
+                Some('t') => s.push('\t'),
+                Some('b') => s.push('\u{8}'),
+                Some('f') => s.push('\u{c}'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let code = u32::from_str_radix(&hex, 16).map_err(|_| format!("bad escape \\u{}", hex))?;
+                    // Surrogate pairs aren't combined, fe never writes them.
+                    s.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                },
+                Some(c) => s.push(c),
+                None => return Err(String::from("unterminated string")),
+            },
+            Some(c) => s.push(c),
+            None => return Err(String::from("unterminated string")),
+        }
+    }
+}
@@ -1,39 +1,295 @@
 #[macro_use]
 extern crate clap;
 extern crate glob;
+extern crate libc;
 extern crate regex;
 extern crate term_painter;
-use clap::App;
+use clap::{App, Arg, ArgMatches};
+use std::fs;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::SystemTime;
 
 mod find;
+mod git;
+mod cache;
+#[cfg(target_os = "linux")]
+mod daemon;
 mod ignore;
+mod index;
 mod display;
+mod exec;
+mod explain;
+mod expr;
 mod fileinfo;
+mod filter;
+mod interactive;
+// Only the daemon reads JSON.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+mod json;
 mod log;
+mod mounts;
+mod name;
+mod stats;
+mod walk;
 
 use log::*;
 
-#[derive(Debug)]
+// The daemon watches directories with inotify, elsewhere there's never one to ask.
+#[cfg(not(target_os = "linux"))]
+mod daemon {
+    use std::path;
+    use find::Match;
+
+    pub fn run(_: &super::Options) -> bool {
+        eprintln!("fe: the daemon needs inotify, which this system doesn't have");
+        false
+    }
+
+    pub fn query(_: &str, _: &super::Options) -> Option<Vec<(path::PathBuf, Match)>> {
+        None
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum SearchType {
     Fuzzy,
     Regex,
     Exact,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+pub enum WordBoundaries {
+    Separators,
+    CamelCase,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    // Raw paths terminated by NUL instead of new lines, for `xargs -0`.
+    Print0,
+    // A JSON array of objects, one per path.
+    Json,
+    // One JSON object per line.
+    Ndjson,
+}
+
+#[derive(Debug, Clone)]
 pub struct Options {
     verbose: bool,
     very_verbose: bool,
     insensitive: bool,
+    // Match patterns without upper case letters case insensitively.
+    smart_case: bool,
     search_names_only: bool,
     no_colors: bool,
+    raw_names: bool,
+    sort: bool,
+    stats: bool,
+    // Search the index built by `fe index build` when it's up to date instead of walking directories.
+    use_index: bool,
+    // Ask a running `fe daemon` of the search directory instead of searching.
+    use_daemon: bool,
+    // Remember directory listings between searches so repeat searches only read what changed.
+    cache: bool,
+    threads: usize,
     search_type: SearchType,
+    word_boundaries: WordBoundaries,
     search_dir: String,
+    output: OutputFormat,
+    exec: Option<exec::CommandTemplate>,
+    // Only print paths of these types, or of any type if it's empty.
+    types: Vec<fileinfo::FileType>,
+    // Size, time and permission filters.
+    filters: filter::Filters,
+    // The `--where` expression paths have to match.
+    expression: Option<Arc<expr::Expr>>,
+    // How deep to search, entries of the search directory are at depth 1.
+    max_depth: Option<usize>,
+    min_depth: usize,
+    // Directories not to search in, and whether to still print them.
+    prune: Vec<glob::Pattern>,
+    print_pruned: bool,
+    // Search in directories symbolic links point to.
+    follow: bool,
+    // Only search directories on the file system of the search directory.
+    one_file_system: bool,
+    // Only search directories on, or not on, file systems of these types.
+    fs_types: Vec<String>,
+    exclude_fs_types: Vec<String>,
+    // Also search files and directories whose names start with a dot.
+    show_hidden: bool,
+    // Don't read any ignore files, or only version control's, and don't skip .git directories.
+    no_ignore: bool,
+    no_ignore_vcs: bool,
+    // The names of the ignore files read in every directory, lowest precedence first.
+    ignore_files: Vec<String>,
+    // Match ignore files' rules case insensitively, which git repositories with `core.ignorecase`
+    // do anyway.
+    ignore_file_case_insensitive: bool,
+    // `--exclude` and `--include` globs as .gitignore lines, includes negated, in the order given.
+    overrides: Vec<String>,
+    // Keys to press in the interactive picker instead of reading them from the terminal.
+    keys: Option<String>,
+}
+
+impl Options {
+
+    fn limits_walk(&self) -> bool {
+        //! Whether some directories aren't searched because of their depth, `--prune` or their file
+        //! system.
+        self.max_depth.is_some() || !self.prune.is_empty() || self.one_file_system || !self.fs_types.is_empty()
+            || !self.exclude_fs_types.is_empty()
+    }
+
+    fn walks_every_path(&self) -> bool {
+        //! Whether a search looks at the paths indexes and the daemon know about: every directory
+        //! is searched, without following symbolic links, and every depth is printed.
+        !self.limits_walk() && self.min_depth == 0 && !self.follow && self.default_ignores()
+    }
+
+    fn default_ignores(&self) -> bool {
+        //! Whether paths are ignored like indexes, the daemon and the walk cache assume: by every
+        //! ignore file fe reads by default and nothing else.
+        self.git_ignores() && self.overrides.is_empty() && !self.ignore_file_case_insensitive
+            && self.ignore_files.iter().map(String::as_str).eq(ignore::IGNORE_FILES.iter().cloned())
+    }
+
+    fn git_ignores(&self) -> bool {
+        //! Whether .gitignore files and git's other ignore files are read and .git is skipped.
+        !self.no_ignore && !self.no_ignore_vcs
+    }
+
+    fn needs_file_info(&self) -> bool {
+        //! Whether printing paths needs their file info, for colours or for JSON output.
+        match self.output {
+            OutputFormat::Text => !self.no_colors,
+            OutputFormat::Print0 => false,
+            OutputFormat::Json | OutputFormat::Ndjson => true,
+        }
+    }
+
+    #[cfg(test)]
+    fn for_tests() -> Options {
+        //! The options of `fe` without arguments, except that only the current directory is
+        //! searched, on one thread, without colours, indexes or the daemon.
+        Options {
+            verbose: false,
+            very_verbose: false,
+            insensitive: false,
+            smart_case: false,
+            search_names_only: false,
+            no_colors: true,
+            raw_names: false,
+            sort: true,
+            stats: false,
+            use_index: false,
+            use_daemon: false,
+            cache: false,
+            threads: 1,
+            search_type: SearchType::Fuzzy,
+            word_boundaries: WordBoundaries::CamelCase,
+            search_dir: "./".to_string(),
+            output: OutputFormat::Text,
+            exec: None,
+            types: vec![],
+            filters: filter::Filters::default(),
+            expression: None,
+            max_depth: None,
+            min_depth: 0,
+            prune: vec![],
+            print_pruned: false,
+            follow: false,
+            one_file_system: false,
+            fs_types: vec![],
+            exclude_fs_types: vec![],
+            show_hidden: false,
+            no_ignore: false,
+            no_ignore_vcs: false,
+            ignore_files: ignore::IGNORE_FILES.iter().map(|name| name.to_string()).collect(),
+            ignore_file_case_insensitive: false,
+            overrides: vec![],
+            keys: None,
+        }
+    }
+}
+
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    //! An empty directory named after the test using it, for tests that need files.
+    let dir = std::env::temp_dir().join(format!("fe-test-{}-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn exec_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    //! The `--exec` arguments are defined here because cli.yaml can't set a value terminator.
+    let exec_arg = |name, long, help| Arg::with_name(name)
+        .long(long)
+        .help(help)
+        .takes_value(true)
+        .multiple(true)
+        .allow_hyphen_values(true)
+        .value_terminator(";")
+        .value_name("cmd");
+    vec![
+        exec_arg("exec", "exec", "Run a command for each search result instead of printing it, in parallel. In the command \
+{} is replaced with the path, {/} with its file name, {//} with its parent directory, {.} with the path without its \
+extension and {/.} with the file name without its extension. The path is added to the end if there are no placeholders. Ends at a ; argument or the end of the command line.")
+            .conflicts_with_all(&["exec_batch", "interactive", "json", "ndjson", "print0"]),
+        exec_arg("exec_batch", "exec-batch", "Like --exec but run the command once with all search results.")
+            .conflicts_with_all(&["interactive", "json", "ndjson", "print0"]),
+    ]
+}
+
+fn filters(matches: &ArgMatches) -> Result<filter::Filters, String> {
+    let now = SystemTime::now();
+    let mut filters = filter::Filters {
+        sizes: matches.values_of("size").map_or(Ok(vec![]), |sizes| sizes.map(filter::parse_size).collect())?,
+        changed_after: matches.value_of("changed_within").map(|t| filter::parse_time(t, now)).transpose()?,
+        changed_before: matches.value_of("changed_before").map(|t| filter::parse_time(t, now)).transpose()?,
+        perm: matches.value_of("perm").map(filter::parse_perm).transpose()?,
+    };
+    if let Some(reference) = matches.value_of("newer") {
+        let modified = fs::metadata(reference)
+            .and_then(|m| m.modified())
+            .map_err(|e| format!("can't get the modification time of {}: {}", reference, e))?;
+        // Both have to hold, so the later one is what counts.
+        filters.changed_after = Some(filters.changed_after.map_or(modified, |after| after.max(modified)));
+    }
+    Ok(filters)
+}
+
+fn overrides(matches: &ArgMatches) -> Result<Vec<String>, String> {
+    //! Turns `--exclude` and `--include` into ignore file lines, in the order they were given so
+    //! later ones win like later lines of an ignore file do.
+    let mut overrides = vec![];
+    for (name, include) in &[("exclude", false), ("include", true)] {
+        let (globs, indices) = match (matches.values_of(name), matches.indices_of(name)) {
+            (Some(g), Some(i)) => (g, i),
+            _ => continue,
+        };
+        for (glob, index) in globs.zip(indices) {
+            glob::Pattern::new(glob.trim_start_matches('/').trim_end_matches('/'))
+                .map_err(|e| format!("invalid --{} glob {}: {}", name, glob, e))?;
+            let rule = match (*include, glob.starts_with('!')) {
+                (true, _) => format!("!{}", glob),
+                (false, true) => format!("\\{}", glob),
+                (false, false) => glob.to_string(),
+            };
+            overrides.push((index, rule));
+        }
+    }
+    overrides.sort();
+    Ok(overrides.into_iter().map(|(_, rule)| rule).collect())
 }
 
 fn main() {
     let yaml = load_yaml!("cli.yaml");
-    let matches = App::from_yaml(yaml).get_matches();
+    let matches = App::from_yaml(yaml).args(&exec_args()).get_matches();
 
     let mut search_type = SearchType::Fuzzy;
     if matches.is_present("regex") {
@@ -42,19 +298,139 @@ fn main() {
         search_type = SearchType::Exact;
     }
 
-    let options = Options {
+    let word_boundaries = if matches.is_present("separator_words") {
+        WordBoundaries::Separators
+    } else {
+        WordBoundaries::CamelCase
+    };
+
+    let depth = |name| matches.value_of(name).map(|_| value_t!(matches, name, usize).unwrap_or_else(|e| e.exit()));
+    let prune = matches.values_of("prune").map_or(vec![], |patterns| patterns
+        .map(|p| glob::Pattern::new(p).unwrap_or_else(|e| {
+            let description = format!("invalid --prune pattern {}: {}", p, e);
+            clap::Error::with_description(&description, clap::ErrorKind::InvalidValue).exit()
+        }))
+        .collect());
+
+    let threads = match matches.value_of("threads") {
+        Some(_) => value_t!(matches, "threads", usize).unwrap_or_else(|e| e.exit()),
+        None => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+    };
+
+    let mut output = OutputFormat::Text;
+    if matches.is_present("print0") {
+        output = OutputFormat::Print0;
+    } else if matches.is_present("json") {
+        output = OutputFormat::Json;
+    } else if matches.is_present("ndjson") {
+        output = OutputFormat::Ndjson;
+    }
+
+    let exec = if let Some(args) = matches.values_of("exec") {
+        Some(exec::CommandTemplate::new(args.map(String::from).collect(), false))
+    } else {
+        matches.values_of("exec_batch").map(|args| exec::CommandTemplate::new(args.map(String::from).collect(), true))
+    };
+
+    let mut options = Options {
         verbose: matches.is_present("verbose"),
         very_verbose: matches.occurrences_of("verbose") > 1,
         insensitive: matches.is_present("insensitive"),
+        smart_case: matches.is_present("smart_case"),
         search_names_only: matches.is_present("name"),
         no_colors: matches.is_present("plain"),
-        search_type: search_type,
+        raw_names: matches.is_present("raw_names"),
+        sort: !matches.is_present("no_sort"),
+        stats: matches.is_present("stats"),
+        use_index: !matches.is_present("no_index"),
+        use_daemon: !matches.is_present("no_daemon"),
+        cache: matches.is_present("cache"),
+        threads,
+        search_type,
+        word_boundaries,
         search_dir: matches.value_of("search_dir").unwrap_or("./").to_string(),
+        output,
+        exec,
+        types: matches.values_of("type").map_or(vec![], |types| types.filter_map(fileinfo::FileType::parse).collect()),
+        filters: filters(&matches).unwrap_or_else(|e| clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit()),
+        expression: None,
+        max_depth: depth("max_depth"),
+        min_depth: depth("min_depth").unwrap_or(0),
+        prune,
+        print_pruned: matches.is_present("print_pruned"),
+        follow: matches.is_present("follow"),
+        one_file_system: matches.is_present("one_file_system"),
+        show_hidden: matches.is_present("hidden") || matches.occurrences_of("unrestricted") > 1,
+        no_ignore: matches.is_present("no_ignore") || matches.is_present("unrestricted"),
+        no_ignore_vcs: matches.is_present("no_ignore_vcs"),
+        ignore_files: matches.values_of("ignore_files").map_or_else(
+            || ignore::IGNORE_FILES.iter().map(|name| name.to_string()).collect(),
+            |names| names.map(String::from).collect()),
+        ignore_file_case_insensitive: matches.is_present("ignore_file_case_insensitive"),
+        overrides: overrides(&matches).unwrap_or_else(|e| clap::Error::with_description(&e, clap::ErrorKind::InvalidValue).exit()),
+        fs_types: matches.values_of("fs_type").map_or(vec![], |types| types.map(String::from).collect()),
+        exclude_fs_types: matches.values_of("exclude_fs_type").map_or(vec![], |types| types.map(String::from).collect()),
+        keys: matches.value_of("keys").map(String::from),
     };
 
+    if !options.fs_types.is_empty() || !options.exclude_fs_types.is_empty() {
+        if let Err(e) = mounts::check_types_known() {
+            clap::Error::with_description(&e, clap::ErrorKind::Io).exit();
+        }
+    }
+
+    // Expressions are parsed once everything else is known, their patterns are matched with the
+    // same options as the search pattern.
+    if let Some(source) = matches.value_of("where") {
+        match expr::parse(source, &options) {
+            Ok(expression) => options.expression = Some(Arc::new(expression)),
+            Err(e) => {
+                let description = format!("invalid --where expression, {} at column {}:\n\n    {}\n    {}^",
+                                          e.message, e.column + 1, source, " ".repeat(e.column));
+                clap::Error::with_description(&description, clap::ErrorKind::InvalidValue).exit();
+            },
+        }
+    }
+
+    if let Some(index_matches) = matches.subcommand_matches("index") {
+        if index_matches.subcommand_matches("build").is_some() {
+            v(format!("Building the index with options: {:?}", options), &options);
+            if !index::build(&options) {
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if matches.subcommand_matches("daemon").is_some() {
+        v(format!("Starting a daemon with options: {:?}", options), &options);
+        if !daemon::run(&options) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(target) = matches.value_of("explain") {
+        v(format!("Explaining {} with options: {:?}", target, options), &options);
+        if !explain::explain(target, matches.value_of("pattern").unwrap_or(""), &options) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if matches.is_present("interactive") {
+        v(format!("Picking interactively with options: {:?}", options), &options);
+        if !interactive::run(matches.value_of("pattern").unwrap_or(""), &options) {
+            process::exit(1);
+        }
+        return;
+    }
+
     // Unwrap in pattern is safe, clap guarantees it.
     let pattern = match matches.value_of("pattern") {
         Some(p) => p,
+        // Searching with only an expression searches every path.
+        None if options.expression.is_some() => "",
         _ => {
             v(format!("Listing files with options: {:?}", options), &options);
             find::list(".", &options);
@@ -67,5 +443,7 @@ fn main() {
         return;
     }
     v(format!("Search pattern is: {}, options: {:?}", pattern, options), &options);
-    find::find(pattern, &options);
+    if !find::find(pattern, &options) {
+        process::exit(1);
+    }
 }
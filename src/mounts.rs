@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path;
+use log::*;
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+// Which file systems directories may be searched on, for `--one-file-system`, `--fs-type` and
+// `--exclude-fs-type`.
+pub struct FileSystems {
+    // The device the search directory is on, with `--one-file-system`.
+    root_device: Option<u64>,
+    // The file system type of each mounted device, when searching by file system type.
+    types: Option<HashMap<u64, String>>,
+}
+
+impl FileSystems {
+
+    pub fn new (root: &path::Path, options: &super::Options) -> FileSystems {
+        let root_device = if options.one_file_system {
+            match fs::metadata(root) {
+                Ok(m) => Some(m.dev()),
+                Err(e) => {
+                    v(format!("Failed to get the device of {:?}: {}", root, e), options);
+                    None
+                },
+            }
+        } else {
+            None
+        };
+        let types = if options.fs_types.is_empty() && options.exclude_fs_types.is_empty() {
+            None
+        } else {
+            match fs::read_to_string(MOUNTINFO) {
+                Ok(mountinfo) => Some(parse_mountinfo(&mountinfo)),
+                Err(e) => {
+                    v(format!("Failed to read {}, file system types are unknown: {}", MOUNTINFO, e), options);
+                    Some(HashMap::new())
+                },
+            }
+        };
+        FileSystems {
+            root_device,
+            types,
+        }
+    }
+
+    pub fn is_active (&self) -> bool {
+        //! Whether directories have to be checked at all.
+        self.root_device.is_some() || self.types.is_some()
+    }
+
+    pub fn allows (&self, dir: &path::Path, device: u64, options: &super::Options) -> bool {
+        //! Whether `dir`, which is on `device`, may be searched.
+        if self.root_device.is_some_and(|root_device| root_device != device) {
+            v(format!("Not searching {:?}, it's on another file system.", dir), options);
+            return false;
+        }
+        let types = match self.types {
+            Some(ref t) => t,
+            None => return true,
+        };
+        // Btrfs subvolumes and the like have devices of their own that aren't mounted.
+        let fs_type = types.get(&device).map_or("unknown", |t| t.as_str());
+        let is = |given: &String| fs_type == given || fs_type.starts_with(&format!("{}.", given));
+        if !options.fs_types.is_empty() && !options.fs_types.iter().any(is) {
+            v(format!("Not searching {:?}, its file system is {}.", dir, fs_type), options);
+            return false;
+        }
+        if options.exclude_fs_types.iter().any(is) {
+            v(format!("Not searching {:?}, its file system is {}.", dir, fs_type), options);
+            return false;
+        }
+        true
+    }
+}
+
+pub fn check_types_known () -> Result<(), String> {
+    //! Whether file system types can be found out, they're read from mountinfo. Without it every
+    //! directory's type would be unknown and `--fs-type` would skip them all.
+    fs::File::open(MOUNTINFO)
+        .map(|_| ())
+        .map_err(|e| format!("can't read {} to find out the types of file systems: {}", MOUNTINFO, e))
+}
+
+fn parse_mountinfo (mountinfo: &str) -> HashMap<u64, String> {
+    //! Maps devices to the type of their file system. Lines of mountinfo look like
+    //! `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`: the
+    //! device's major and minor number is the third field, and the type follows the `-` that ends
+    //! the optional fields.
+    let mut types = HashMap::new();
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let device = fields.get(2).and_then(|d| {
+            let (major, minor) = d.split_once(':')?;
+            Some(make_device(major.parse().ok()?, minor.parse().ok()?))
+        });
+        let fs_type = fields.iter().skip(6).position(|f| *f == "-").and_then(|i| fields.get(i + 7)).filter(|t| !t.is_empty());
+        if let (Some(device), Some(fs_type)) = (device, fs_type) {
+            types.insert(device, fs_type.to_string());
+        }
+    }
+    types
+}
+
+fn make_device (major: u64, minor: u64) -> u64 {
+    //! `st_dev` of a device number, as glibc's makedev encodes it.
+    ((major & 0xfffff000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffffff00) << 12) | (minor & 0xff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc;
+
+    #[test]
+    fn mountinfo () {
+        let types = parse_mountinfo("\
+            22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+            36 22 0:32 / /proc rw,nosuid - proc proc rw\n\
+            40 22 0:45 / /mnt/my\\040disk rw master:3 shared:7 propagate_from:2 - fuse.sshfs me@host: rw\n\
+            41 22 259:65536 / /data rw - btrfs /dev/nvme0n1p1 rw\n\
+            a line that isn't mountinfo\n\
+            42 22 0:46 / /broken rw - \n");
+        assert_eq!(types.len(), 4);
+        assert_eq!(types[&make_device(8, 1)], "ext4");
+        // No optional fields, and several of them, before the `-`.
+        assert_eq!(types[&make_device(0, 32)], "proc");
+        assert_eq!(types[&make_device(0, 45)], "fuse.sshfs");
+        assert_eq!(types[&make_device(259, 65536)], "btrfs");
+    }
+
+    #[test]
+    fn devices () {
+        for &(major, minor) in &[(8, 1), (0, 45), (259, 65536), (0x12345, 0x6789a), (0xfffff, 0xfffff)] {
+            assert_eq!(make_device(major, minor), libc::makedev(major as u32, minor as u32));
+        }
+        assert_eq!(make_device(8, 1), 0x801);
+        assert_eq!(make_device(0x12345, 0x6789a), 0x1_2000_6783_459a);
+    }
+
+    #[test]
+    fn this_systems_mounts () {
+        // The root directory's file system is one of the mounted ones, wherever this runs.
+        if let Ok(mountinfo) = fs::read_to_string(MOUNTINFO) {
+            let root = fs::metadata("/").unwrap();
+            assert!(parse_mountinfo(&mountinfo).contains_key(&root.dev()));
+        }
+    }
+}
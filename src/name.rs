@@ -0,0 +1,151 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path;
+use std::str;
+
+// A path or file name decoded for matching and printing. File names are bytes and not always
+// UTF-8, so any bytes that aren't part of valid UTF-8 are decoded as Latin-1 characters, which
+// lets them be matched, and remembered so they can be escaped when printed.
+pub struct Name<'a> {
+    pub bytes: Cow<'a, [u8]>,
+    pub text: String,
+    // Character indices in `text` that were decoded from invalid bytes, in order.
+    raw: Vec<usize>,
+}
+
+impl<'a> Name<'a> {
+
+    pub fn new (bytes: Cow<'a, [u8]>) -> Name<'a> {
+        let mut text = String::new();
+        let mut raw = vec![];
+        let mut rest = &bytes[..];
+        loop {
+            match str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                },
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    // Safe as `from_utf8` just said so.
+                    text.push_str(str::from_utf8(valid).unwrap());
+                    let invalid_len = e.error_len().unwrap_or(invalid.len());
+                    for b in &invalid[..invalid_len] {
+                        raw.push(text.chars().count());
+                        text.push(*b as char);
+                    }
+                    rest = &invalid[invalid_len..];
+                },
+            }
+        }
+        Name {
+            bytes,
+            text,
+            raw,
+        }
+    }
+
+    pub fn from_path (path: &'a path::Path) -> Name<'a> {
+        //! The name as fe prints paths, without a leading `./`.
+        let bytes = os_str_bytes(path.as_os_str());
+        let bytes = match bytes {
+            Cow::Borrowed(b) if b.starts_with(b"./") => Cow::Borrowed(&b[2..]),
+            Cow::Owned(ref b) if b.starts_with(b"./") => Cow::Owned(b[2..].to_vec()),
+            b => b,
+        };
+        Name::new(bytes)
+    }
+
+    pub fn from_file_name (path: &'a path::Path) -> Name<'a> {
+        Name::new(os_str_bytes(path.file_name().unwrap_or_else(|| OsStr::new(""))))
+    }
+
+    pub fn is_raw (&self, char_index: usize) -> bool {
+        self.raw.binary_search(&char_index).is_ok()
+    }
+
+    pub fn char_indices_of_bytes (&self, spans: &[(usize, usize)]) -> Vec<usize> {
+        //! Converts byte spans of `bytes` to the indices of the characters of `text` they cover.
+        let mut indices = vec![];
+        let mut byte = 0;
+        for (i, c) in self.text.chars().enumerate() {
+            if spans.iter().any(|&(start, end)| byte >= start && byte < end) {
+                indices.push(i);
+            }
+            byte += if self.is_raw(i) { 1 } else { c.len_utf8() };
+        }
+        indices
+    }
+
+    pub fn escaped_chars (&self) -> Vec<String> {
+        //! Each character of `text` as it should be printed, bytes that weren't valid UTF-8 as `\xNN`.
+        self.text.chars()
+            .enumerate()
+            .map(|(i, c)| if self.is_raw(i) { format!("\\x{:02X}", c as u32) } else { c.to_string() })
+            .collect()
+    }
+
+    pub fn escaped (&self) -> Cow<'_, str> {
+        if self.raw.is_empty() {
+            return Cow::Borrowed(&self.text);
+        }
+        Cow::Owned(self.escaped_chars().concat())
+    }
+
+    pub fn is_utf8 (&self) -> bool {
+        self.raw.is_empty()
+    }
+
+    pub fn escaped_positions (&self, positions: &[usize]) -> Vec<usize> {
+        //! Converts character indices in `text` to indices in `escaped`. A byte that wasn't valid
+        //! UTF-8 covers all the characters of its `\xNN`.
+        if self.raw.is_empty() {
+            return positions.to_vec();
+        }
+        let mut escaped = vec![];
+        let mut at = 0;
+        for (i, c) in self.escaped_chars().iter().enumerate() {
+            let len = c.chars().count();
+            if positions.contains(&i) {
+                escaped.extend(at..at + len);
+            }
+            at += len;
+        }
+        escaped
+    }
+}
+
+#[cfg(unix)]
+fn os_str_bytes (s: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(s.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn os_str_bytes (s: &OsStr) -> Cow<'_, [u8]> {
+    match s.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaping () {
+        let name = Name::new(Cow::Borrowed(b"a\xffb\xc3"));
+        assert!(!name.is_utf8());
+        assert_eq!(name.text, "a\u{ff}b\u{c3}");
+        assert_eq!(name.escaped(), "a\\xFFb\\xC3");
+        assert_eq!(name.char_indices_of_bytes(&[(1, 3)]), vec![1, 2]);
+        assert_eq!(name.escaped_positions(&[0, 1, 2]), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(name.escaped_positions(&[3]), vec![6, 7, 8, 9]);
+
+        let name = Name::new(Cow::Borrowed("é/b".as_bytes()));
+        assert!(name.is_utf8());
+        assert_eq!(name.escaped(), "é/b");
+        assert_eq!(name.escaped_positions(&[0, 2]), vec![0, 2]);
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use display;
+
+// Counters shown by `--stats`. They're updated from every search thread so they're atomics, and
+// only ever read once searching is done.
+pub static DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+// Directories that hadn't changed since the last search, so their entries came from the walk cache.
+pub static CACHED_DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+pub static ENTRIES: AtomicUsize = AtomicUsize::new(0);
+pub static STAT_CALLS: AtomicUsize = AtomicUsize::new(0);
+// Paths searched in the index built by `fe index build` instead of in directories.
+pub static INDEXED: AtomicUsize = AtomicUsize::new(0);
+// Stat calls that would have been made if every entry were stat'ed to see if it is a directory, and
+// every printed path stat'ed twice, with and without following links, to pick its colour.
+pub static STAT_CALLS_SAVED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn add (counter: &AtomicUsize, n: usize) {
+    counter.fetch_add(n, Ordering::Relaxed);
+}
+
+pub fn print (options: &super::Options) {
+    if !options.stats {
+        return;
+    }
+    let get = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+    display::print_stats(&[
+        ("directories read", get(&DIRECTORIES)),
+        ("directories cached", get(&CACHED_DIRECTORIES)),
+        ("entries examined", get(&ENTRIES)),
+        ("stat calls", get(&STAT_CALLS)),
+        ("indexed paths searched", get(&INDEXED)),
+        ("stat calls saved", get(&STAT_CALLS_SAVED)),
+    ]);
+}
@@ -0,0 +1,94 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// How long an idle worker waits before looking for work to steal again.
+const IDLE_WAIT: Duration = Duration::from_micros(50);
+
+// A work stealing queue of directories, or anything else, shared by a pool of threads. Each
+// thread takes work from the back of its own queue, so it walks depth first and keeps its
+// working set small, and when that runs out steals from the front of the other threads'
+// queues, where the biggest unwalked subtrees are.
+pub struct Walker<T> {
+    queues: Vec<Mutex<VecDeque<T>>>,
+    // Items pushed but not yet finished being visited. The walk is over when this reaches zero.
+    pending: AtomicUsize,
+    // Set once the walk should end early, after which nothing more is queued.
+    stopped: AtomicBool,
+}
+
+impl<T: Send> Walker<T> {
+
+    pub fn new (threads: usize) -> Walker<T> {
+        let threads = threads.max(1);
+        Walker {
+            queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            pending: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    pub fn push (&self, worker: usize, item: T) {
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        self.queues[worker].lock().unwrap().push_back(item);
+    }
+
+    pub fn stop (&self) {
+        //! Drops everything still queued, items already being visited are finished.
+        self.stopped.store(true, Ordering::SeqCst);
+        for queue in &self.queues {
+            let mut queue = queue.lock().unwrap();
+            self.pending.fetch_sub(queue.len(), Ordering::SeqCst);
+            queue.clear();
+        }
+    }
+
+    pub fn run<F> (&self, visit: F) where F: Fn(usize, T) + Sync {
+        //! Visits every item pushed, including items pushed by `visit` itself, on one thread per
+        //! queue. `visit` is given the index of the worker it runs on to push new items with.
+        if self.queues.len() == 1 {
+            self.work(0, &visit);
+            return;
+        }
+        thread::scope(|scope| {
+            for worker in 0..self.queues.len() {
+                let visit = &visit;
+                scope.spawn(move || self.work(worker, visit));
+            }
+        });
+    }
+
+    fn work<F> (&self, worker: usize, visit: &F) where F: Fn(usize, T) + Sync {
+        loop {
+            match self.next(worker) {
+                Some(item) => {
+                    visit(worker, item);
+                    self.pending.fetch_sub(1, Ordering::SeqCst);
+                },
+                None => {
+                    if self.pending.load(Ordering::SeqCst) == 0 {
+                        return;
+                    }
+                    // Another thread is still visiting something that may push more work.
+                    thread::sleep(IDLE_WAIT);
+                },
+            }
+        }
+    }
+
+    fn next (&self, worker: usize) -> Option<T> {
+        if let Some(item) = self.queues[worker].lock().unwrap().pop_back() {
+            return Some(item);
+        }
+        let threads = self.queues.len();
+        (1..threads)
+            .map(|i| (worker + i) % threads)
+            .filter_map(|victim| self.queues[victim].lock().unwrap().pop_front())
+            .next()
+    }
+}